@@ -0,0 +1,196 @@
+//! Syntax tree produced by [`crate::parse`].
+//!
+//! Nothing in here touches the filesystem or the environment: words keep their
+//! quoting and expansion parts, and redirect targets are not opened until the
+//! command is executed.
+
+/// Sequence of and-or lists separated by `;`, `&` or newlines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct List {
+    pub items: Vec<ListItem>,
+    /// Comment lines after the last item
+    pub comments: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    /// Terminated by `&`
+    pub background: bool,
+    /// Comment lines before the item, without the leading `#`
+    pub comments: Vec<String>,
+    /// Comment at the end of the item's line
+    pub comment: Option<String>,
+}
+
+/// Pipelines joined by `&&` and `||`, evaluated left to right.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    /// `! pipeline`, inverts the exit status
+    pub negated: bool,
+    /// `time [-p] pipeline`
+    pub time: Option<TimeFormat>,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// Uses `TIMEFORMAT`
+    Default,
+    /// `time -p`, the POSIX output format
+    Posix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound {
+        body: CompoundCommand,
+        redirects: Vec<Redirect>,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// `name=value` words before the command name
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompoundCommand {
+    /// `{ list; }`
+    BraceGroup(List),
+    /// `( list )`
+    Subshell(List),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while list; do list; done`
+    While { condition: List, body: List },
+    /// `until list; do list; done`
+    Until { condition: List, body: List },
+    /// `for name [in word...]; do list; done`
+    For {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: List,
+    },
+    /// `case word in [(]pattern[|pattern]...) list;; ... esac`
+    Case { word: Word, items: Vec<CaseItem> },
+    /// `[[ expression ]]`
+    Conditional(ConditionalExpr),
+}
+
+/// Expression of `[[ ]]`, whose words are neither split nor globbed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionalExpr {
+    /// `-op word`, such as `-f file`
+    Unary {
+        op: String,
+        operand: Word,
+    },
+    /// `word op word`, the right side of `==`, `!=` and `=~` is a pattern
+    Binary {
+        lhs: Word,
+        op: String,
+        rhs: Word,
+    },
+    /// A word alone is true if it is not empty
+    Word(Word),
+    Not(Box<ConditionalExpr>),
+    And(Box<ConditionalExpr>, Box<ConditionalExpr>),
+    Or(Box<ConditionalExpr>, Box<ConditionalExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Redirect {
+    Input { n: i32, word: Word },
+    Output { n: i32, word: Word },
+    AppendOutput { n: i32, word: Word },
+}
+
+/// A single shell word, made of adjacent quoted and unquoted parts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word(pub Vec<WordPart>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    /// Unquoted text
+    Literal(String),
+    /// Character preceded by a backslash outside of quotes
+    Escaped(char),
+    /// `'...'`
+    SingleQuoted(String),
+    /// `$'...'`, with its backslash escapes already decoded
+    AnsiCQuoted(String),
+    /// `"..."`, only contains `Literal` and `Parameter` parts
+    DoubleQuoted(Vec<WordPart>),
+    /// `$name` or `${name}`
+    Parameter(String),
+}
+
+impl ListItem {
+    pub fn new(and_or: AndOr, background: bool) -> ListItem {
+        ListItem {
+            and_or,
+            background,
+            comments: vec![],
+            comment: None,
+        }
+    }
+}
+
+impl Word {
+    /// Text of the word if it is made of a single unquoted literal,
+    /// used to recognise reserved words and names.
+    pub fn as_literal(&self) -> Option<&str> {
+        match &self.0[..] {
+            [WordPart::Literal(s)] => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Word {
+    fn from(s: &str) -> Word {
+        Word(vec![WordPart::Literal(s.to_string())])
+    }
+}
+
+impl Redirect {
+    pub fn word(&self) -> &Word {
+        match self {
+            Redirect::Input { word, .. }
+            | Redirect::Output { word, .. }
+            | Redirect::AppendOutput { word, .. } => word,
+        }
+    }
+}
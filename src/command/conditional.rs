@@ -0,0 +1,316 @@
+use std::{
+    ffi::CString,
+    fs::{self, Metadata},
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+};
+
+use crate::{ast::ConditionalExpr, state::ShellState};
+
+use super::expand::{expand_pattern, expand_regex, expand_word};
+
+/// Operators taking a single operand
+const UNARY_OPERATORS: [&str; 21] = [
+    "-e", "-f", "-d", "-r", "-w", "-x", "-s", "-L", "-h", "-b", "-c", "-p", "-S", "-u", "-g", "-k",
+    "-t", "-z", "-n", "-O", "-G",
+];
+
+/// Operators between two operands
+const BINARY_OPERATORS: [&str; 14] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// Evaluate the arguments of `test` or `[`.
+///
+/// Up to four arguments are disambiguated by their count as POSIX specifies,
+/// so that `test -n` or `test ! = x` mean what they say; longer expressions
+/// are parsed with `!` binding tighter than `-a`, itself tighter than `-o`.
+pub(super) fn evaluate(args: &[String]) -> Result<bool, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        [] => Ok(false),
+        [arg] => Ok(!arg.is_empty()),
+        ["!", arg] => Ok(arg.is_empty()),
+        [op, arg] if UNARY_OPERATORS.contains(&op) => unary_test(op, arg),
+        [op, _] => Err(format!("{op}: unary operator expected")),
+        [lhs, op, rhs] if BINARY_OPERATORS.contains(&op) => binary_test(lhs, op, rhs),
+        [lhs, "-a", rhs] => Ok(!lhs.is_empty() && !rhs.is_empty()),
+        [lhs, "-o", rhs] => Ok(!lhs.is_empty() || !rhs.is_empty()),
+        ["!", ..] if args.len() <= 4 => evaluate_strs(&args[1..]).map(|result| !result),
+        ["(", arg, ")"] => Ok(!arg.is_empty()),
+        ["(", _, _, ")"] => evaluate_strs(&args[1..3]),
+        [_, op, _] => Err(format!("{op}: binary operator expected")),
+        _ => {
+            let mut parser = Parser {
+                args: &args,
+                pos: 0,
+            };
+            let result = parser.or()?;
+            match parser.peek() {
+                None => Ok(result),
+                Some(arg) => Err(format!("{arg}: unexpected argument")),
+            }
+        }
+    }
+}
+
+fn evaluate_strs(args: &[&str]) -> Result<bool, String> {
+    let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+    evaluate(&args)
+}
+
+/// Recursive descent parser for expressions longer than four arguments
+struct Parser<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.args.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let arg = self.args.get(self.pos).copied();
+        self.pos += 1;
+        arg.ok_or_else(|| "argument expected".to_string())
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.peek() == Some("-o") {
+            self.pos += 1;
+            // both sides are parsed to report syntax errors
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.peek() == Some("-a") {
+            self.pos += 1;
+            result &= self.not()?;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        match self.peek() {
+            Some("!") => {
+                self.pos += 1;
+                self.not().map(|result| !result)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let arg = self.next()?;
+        let rest = &self.args[self.pos..];
+        match rest.first() {
+            Some(&op) if BINARY_OPERATORS.contains(&op) && rest.len() > 1 => {
+                self.pos += 2;
+                return binary_test(arg, op, rest[1]);
+            }
+            _ => {}
+        }
+
+        match arg {
+            "(" => {
+                let result = self.or()?;
+                match self.next()? {
+                    ")" => Ok(result),
+                    arg => Err(format!("{arg}: `)' expected")),
+                }
+            }
+            op if UNARY_OPERATORS.contains(&op) && !rest.is_empty() => {
+                let operand = self.next()?;
+                unary_test(op, operand)
+            }
+            arg => Ok(!arg.is_empty()),
+        }
+    }
+}
+
+/// Evaluate the expression of a `[[ ]]` command.
+///
+/// Operands are expanded without field splitting or pathname expansion, the
+/// right side of `==` and `!=` is a pattern and the right side of `=~` an
+/// extended regular expression whose captures are stored in `BASH_REMATCH`.
+pub(super) fn evaluate_conditional(
+    state: &mut ShellState,
+    expr: &ConditionalExpr,
+) -> Result<bool, String> {
+    match expr {
+        ConditionalExpr::Word(word) => Ok(!expand_word(state, word).is_empty()),
+        ConditionalExpr::Not(expr) => evaluate_conditional(state, expr).map(|result| !result),
+        ConditionalExpr::And(lhs, rhs) => {
+            Ok(evaluate_conditional(state, lhs)? && evaluate_conditional(state, rhs)?)
+        }
+        ConditionalExpr::Or(lhs, rhs) => {
+            Ok(evaluate_conditional(state, lhs)? || evaluate_conditional(state, rhs)?)
+        }
+        ConditionalExpr::Unary { op, operand } => {
+            let op = match op.as_str() {
+                "-a" => "-e",
+                op => op,
+            };
+            unary_test(op, &expand_word(state, operand))
+        }
+        ConditionalExpr::Binary { lhs, op, rhs } => {
+            let lhs = expand_word(state, lhs);
+            match op.as_str() {
+                "=" | "==" | "!=" => {
+                    let pattern = expand_pattern(state, rhs);
+                    let matched = match glob::Pattern::new(&pattern) {
+                        Ok(pattern) => pattern.matches(&lhs),
+                        Err(_) => pattern == lhs,
+                    };
+                    Ok(matched == (op != "!="))
+                }
+                "=~" => {
+                    let pattern = expand_regex(state, rhs);
+                    let regex = regex::Regex::new(&pattern).map_err(|err| err.to_string())?;
+                    let captures: Vec<String> = match regex.captures(&lhs) {
+                        Some(captures) => captures
+                            .iter()
+                            .map(|group| group.map_or("", |m| m.as_str()).to_string())
+                            .collect(),
+                        None => vec![],
+                    };
+                    let matched = !captures.is_empty();
+                    state.set_array("BASH_REMATCH", captures);
+                    Ok(matched)
+                }
+                op => binary_test(&lhs, op, &expand_word(state, rhs)),
+            }
+        }
+    }
+}
+
+/// File and string tests with one operand
+pub(super) fn unary_test(op: &str, arg: &str) -> Result<bool, String> {
+    let metadata = || fs::metadata(arg).ok();
+    let has_mode = |mode: u32| metadata().is_some_and(|m| m.permissions().mode() & mode != 0);
+
+    let result = match op {
+        "-z" => arg.is_empty(),
+        "-n" => !arg.is_empty(),
+        "-e" => metadata().is_some(),
+        "-f" => metadata().is_some_and(|m| m.is_file()),
+        "-d" => metadata().is_some_and(|m| m.is_dir()),
+        "-s" => metadata().is_some_and(|m| m.len() > 0),
+        "-L" | "-h" => fs::symlink_metadata(arg).is_ok_and(|m| m.file_type().is_symlink()),
+        "-b" => metadata().is_some_and(|m| m.file_type().is_block_device()),
+        "-c" => metadata().is_some_and(|m| m.file_type().is_char_device()),
+        "-p" => metadata().is_some_and(|m| m.file_type().is_fifo()),
+        "-S" => metadata().is_some_and(|m| m.file_type().is_socket()),
+        "-u" => has_mode(0o4000),
+        "-g" => has_mode(0o2000),
+        "-k" => has_mode(0o1000),
+        "-r" => access(arg, libc::R_OK),
+        "-w" => access(arg, libc::W_OK),
+        "-x" => access(arg, libc::X_OK),
+        // SAFETY: getuid and getgid cannot fail
+        "-O" => metadata().is_some_and(|m| m.uid() == unsafe { libc::getuid() }),
+        "-G" => metadata().is_some_and(|m| m.gid() == unsafe { libc::getgid() }),
+        "-t" => {
+            let fd = integer(arg)?;
+            // SAFETY: isatty accepts any value and only reports whether it is a terminal
+            i32::try_from(fd).is_ok_and(|fd| unsafe { libc::isatty(fd) } == 1)
+        }
+        op => return Err(format!("{op}: unary operator expected")),
+    };
+    Ok(result)
+}
+
+/// String, integer and file comparisons
+pub(super) fn binary_test(lhs: &str, op: &str, rhs: &str) -> Result<bool, String> {
+    let modified = |path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let result = match op {
+        "=" | "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        ">" => lhs > rhs,
+        "-eq" => integer(lhs)? == integer(rhs)?,
+        "-ne" => integer(lhs)? != integer(rhs)?,
+        "-lt" => integer(lhs)? < integer(rhs)?,
+        "-le" => integer(lhs)? <= integer(rhs)?,
+        "-gt" => integer(lhs)? > integer(rhs)?,
+        "-ge" => integer(lhs)? >= integer(rhs)?,
+        "-nt" => match (modified(lhs), modified(rhs)) {
+            (Some(lhs), Some(rhs)) => lhs > rhs,
+            (lhs, rhs) => lhs.is_some() && rhs.is_none(),
+        },
+        "-ot" => match (modified(lhs), modified(rhs)) {
+            (Some(lhs), Some(rhs)) => lhs < rhs,
+            (lhs, rhs) => lhs.is_none() && rhs.is_some(),
+        },
+        "-ef" => match (fs::metadata(lhs), fs::metadata(rhs)) {
+            (Ok(lhs), Ok(rhs)) => same_file(&lhs, &rhs),
+            _ => false,
+        },
+        op => return Err(format!("{op}: binary operator expected")),
+    };
+    Ok(result)
+}
+
+fn integer(s: &str) -> Result<i64, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("{s}: integer expression expected"))
+}
+
+fn same_file(lhs: &Metadata, rhs: &Metadata) -> bool {
+    lhs.dev() == rhs.dev() && lhs.ino() == rhs.ino()
+}
+
+/// Whether the current user has the given permission on `path`
+fn access(path: &str, mode: libc::c_int) -> bool {
+    match CString::new(path) {
+        // SAFETY: the path is a valid NUL terminated string
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn test(args: &str) -> Result<bool, String> {
+        let args: Vec<String> = args.split(' ').map(String::from).collect();
+        evaluate(&args)
+    }
+
+    #[test]
+    fn argument_count() {
+        assert_eq!(evaluate(&[]), Ok(false));
+        assert_eq!(test("-n"), Ok(true));
+        assert_eq!(evaluate(&["".into()]), Ok(false));
+        assert_eq!(test("! -n"), Ok(false));
+        assert_eq!(test("-z abc"), Ok(false));
+        assert_eq!(test("! = x"), Ok(false));
+        assert_eq!(test("a = a"), Ok(true));
+        assert_eq!(test("( a )"), Ok(true));
+        assert_eq!(test("! a = b"), Ok(true));
+        assert_eq!(test("( -z a )"), Ok(false));
+        assert!(test("a b").is_err());
+        assert!(test("a b c").is_err());
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(test("10 -gt 9"), Ok(true));
+        assert_eq!(test("10 > 9"), Ok(false));
+        assert_eq!(test("-1 -le -1"), Ok(true));
+        assert!(test("a -eq 1").is_err());
+        assert_eq!(test("-d / -a -f /"), Ok(false));
+        assert_eq!(test("-d / -o -f /"), Ok(true));
+        assert_eq!(test("! -e /nonexistent -a ( 1 -eq 1 -o x = y )"), Ok(true));
+        assert_eq!(test("x = x -a ! y != y -o z"), Ok(true));
+        assert_eq!(test("/ -ef /."), Ok(true));
+        assert!(test("( a -a b").is_err());
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::PathBuf,
+    str::FromStr,
+    time::Instant,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    ast::{
+        self, AndOr, CompoundCommand, Connector, List, ListItem, Pipeline, Redirect, TimeFormat,
+        Word,
+    },
+    format::{format_and_or, format_pipeline},
+    io::{Io, PErr, PIn, POut, PType},
+    job::{interrupted, INTERRUPTED_STATUS},
+    parse::{parse_lines, parse_with_aliases, ParseError},
+    shell::exit_shell,
+    state::{ShellOption, ShellState},
+    utils::{resolve_command, ResolveError},
+};
+
+use super::{
+    conditional::evaluate_conditional,
+    execute::quote_word,
+    expand::{expand_pattern, expand_word, expand_words, unset_parameter},
+    time::{format_times, CpuTime, DEFAULT_TIMEFORMAT, POSIX_TIMEFORMAT},
+    BuiltinCommand, Command, CommandArgs, InvalidCommand, PathCommand, PipeCommands, StdioCommand,
+    SubshellCommand,
+};
+
+/// Execute every item of the list, returning the status of the last one
+pub(crate) fn execute_list(state: &mut ShellState, list: &List, io: &Io) -> Result<i32> {
+    let mut status = 0;
+    for item in &list.items {
+        if interrupted() || state.returned() {
+            break;
+        }
+        status = match item.background {
+            true => execute_background(state, &item.and_or, io)?,
+            false => execute_and_or(state, &item.and_or, io)?,
+        };
+    }
+    // Ctrl-C stops every enclosing list and loop
+    if interrupted() {
+        status = INTERRUPTED_STATUS;
+        state.set_status(status);
+    }
+    Ok(status)
+}
+
+/// Parse and execute `input` one complete command at a time, so that the
+/// commands before a syntax error run and the aliases defined by a command
+/// apply to the next ones.
+///
+/// A syntax error stops the execution, it is returned with its line number.
+pub(crate) fn execute_input(
+    state: &mut ShellState,
+    input: &str,
+    io: &Io,
+) -> Result<Result<i32, (usize, ParseError)>> {
+    let mut status = 0;
+    let mut command = String::new();
+    let mut line_number = 0;
+    for line in input.split_inclusive('\n') {
+        line_number += 1;
+        command.push_str(line);
+        match parse_lines(&command, state.aliases()) {
+            Err(ParseError::Incomplete) => continue,
+            Err(err) => return Ok(Err((line_number, err))),
+            // blank lines and comments keep the status
+            Ok(list) if list.items.is_empty() => {}
+            Ok(list) => status = execute_list(state, &list, io)?,
+        }
+        command.clear();
+        if interrupted() || state.returned() {
+            return Ok(Ok(status));
+        }
+    }
+    // the last line may be continued by a backslash, or be missing the end
+    // of a compound command
+    if !command.is_empty() {
+        match parse_with_aliases(&command, state.aliases()) {
+            Err(err) => return Ok(Err((line_number, err))),
+            Ok(list) if list.items.is_empty() => {}
+            Ok(list) => status = execute_list(state, &list, io)?,
+        }
+    }
+    Ok(Ok(status))
+}
+
+fn execute_and_or(state: &mut ShellState, and_or: &AndOr, io: &Io) -> Result<i32> {
+    let mut status = execute_pipeline(state, &and_or.first, io)?;
+    let mut last = &and_or.first;
+    for (connector, pipeline) in &and_or.rest {
+        match (connector, status == 0) {
+            (Connector::And, true) | (Connector::Or, false) => {
+                status = execute_pipeline(state, pipeline, io)?;
+                last = pipeline;
+            }
+            _ => {}
+        }
+    }
+    // errexit only applies to the pipeline after the final `&&` or `||`
+    let final_pipeline = and_or
+        .rest
+        .last()
+        .map_or(&and_or.first, |(_, pipeline)| pipeline);
+    if status != 0
+        && state.option(ShellOption::Errexit)
+        && !state.in_condition()
+        && std::ptr::eq(last, final_pipeline)
+        && !last.negated
+    {
+        exit_shell(state, status);
+    }
+    Ok(status)
+}
+
+/// Execute the condition of `if`, `while` or `until`, where errexit does not apply
+fn execute_condition(state: &mut ShellState, condition: &List, io: &Io) -> Result<i32> {
+    state.enter_condition();
+    let status = execute_list(state, condition, io);
+    state.leave_condition();
+    status
+}
+
+fn execute_background(state: &mut ShellState, and_or: &AndOr, io: &Io) -> Result<i32> {
+    // a pipeline of simple commands runs as it would in the foreground,
+    // anything else runs as a whole in a subshell so that the shell goes on
+    let simple = and_or.rest.is_empty()
+        && and_or
+            .first
+            .commands
+            .iter()
+            .all(|command| matches!(command, ast::Command::Simple(_)));
+    let result = match simple {
+        true => pipe_commands(state, &and_or.first, io),
+        false => Ok(subshell_commands(and_or)),
+    };
+    let result = result.and_then(|commands| commands.spawn(state, io));
+    let status = match result {
+        Ok(id) => {
+            // only an interactive shell reports the jobs it starts
+            if state.option(ShellOption::Monitor) {
+                let pid = state.background_pid().unwrap_or_default();
+                io.write_stderr(format!("[{id}] {pid}\n").as_bytes())?;
+            }
+            0
+        }
+        Err(err) => {
+            io.write_stderr(format!("{err:#}\n").as_bytes())?;
+            1
+        }
+    };
+    state.set_status(status);
+    Ok(status)
+}
+
+fn execute_pipeline(state: &mut ShellState, pipeline: &Pipeline, io: &Io) -> Result<i32> {
+    let start = (Instant::now(), CpuTime::now());
+    let result =
+        pipe_commands(state, pipeline, io).and_then(|commands| commands.execute(state, io));
+    let statuses = match result {
+        Ok(statuses) => statuses,
+        Err(err) => {
+            io.write_stderr(format!("{err:#}\n").as_bytes())?;
+            vec![1]
+        }
+    };
+    // with pipefail the last failing command decides, not the last command
+    let status = match state.option(ShellOption::Pipefail) {
+        true => statuses.iter().rev().find(|&&status| status != 0),
+        false => statuses.last(),
+    };
+    let status = status.copied().unwrap_or_default();
+    state.set_array("PIPESTATUS", statuses.iter().map(i32::to_string).collect());
+
+    if let Some(format) = pipeline.time {
+        let format = match format {
+            TimeFormat::Default => state
+                .var("TIMEFORMAT")
+                .unwrap_or_else(|| DEFAULT_TIMEFORMAT.to_string()),
+            TimeFormat::Posix => POSIX_TIMEFORMAT.to_string(),
+        };
+        // an empty format disables the report
+        if !format.is_empty() {
+            let times = format_times(&format, start.0.elapsed(), CpuTime::since(start.1));
+            io.write_stderr(format!("{times}\n").as_bytes())?;
+        }
+    }
+
+    let status = match pipeline.negated {
+        true => i32::from(status == 0),
+        false => status,
+    };
+    state.set_status(status);
+    Ok(status)
+}
+
+pub(super) fn execute_compound(
+    state: &mut ShellState,
+    compound: &CompoundCommand,
+    io: &Io,
+) -> Result<i32> {
+    match compound {
+        CompoundCommand::BraceGroup(list) => execute_list(state, list, io),
+        CompoundCommand::Subshell(_) => {
+            unreachable!("subshells run in a child process, see stdio_command")
+        }
+        CompoundCommand::If {
+            branches,
+            otherwise,
+        } => {
+            for (condition, body) in branches {
+                if execute_condition(state, condition, io)? == 0 {
+                    return execute_list(state, body, io);
+                }
+            }
+            match otherwise {
+                Some(body) => execute_list(state, body, io),
+                None => Ok(0),
+            }
+        }
+        CompoundCommand::While { condition, body } => {
+            execute_loop(state, condition, body, true, io)
+        }
+        CompoundCommand::Until { condition, body } => {
+            execute_loop(state, condition, body, false, io)
+        }
+        CompoundCommand::For { name, words, body } => {
+            check_unset(state, words.iter().flatten(), io)?;
+            let values = match words {
+                Some(words) => expand_words(state, words),
+                // without `in`, the loop is over the positional parameters
+                None => state.positional().to_vec(),
+            };
+            let mut status = 0;
+            for value in values {
+                if interrupted() || state.returned() {
+                    break;
+                }
+                state.set_var(name, value);
+                status = execute_list(state, body, io)?;
+            }
+            Ok(status)
+        }
+        CompoundCommand::Case { word, items } => {
+            check_unset(state, [word], io)?;
+            let word = expand_word(state, word);
+            for item in items {
+                let matched = item.patterns.iter().any(|pattern| {
+                    let pattern = expand_pattern(state, pattern);
+                    match glob::Pattern::new(&pattern) {
+                        Ok(pattern) => pattern.matches(&word),
+                        Err(_) => pattern == word,
+                    }
+                });
+                if matched {
+                    return execute_list(state, &item.body, io);
+                }
+            }
+            Ok(0)
+        }
+        CompoundCommand::Conditional(expr) => match evaluate_conditional(state, expr) {
+            Ok(result) => Ok(i32::from(!result)),
+            Err(err) => {
+                io.write_stderr(format!("[[: {err}\n").as_bytes())?;
+                Ok(2)
+            }
+        },
+    }
+}
+
+/// Run `body` as long as the status of `condition` is zero (`while`) or non zero (`until`)
+fn execute_loop(
+    state: &mut ShellState,
+    condition: &List,
+    body: &List,
+    is_while: bool,
+    io: &Io,
+) -> Result<i32> {
+    let mut status = 0;
+    while (execute_condition(state, condition, io)? == 0) == is_while
+        && !interrupted()
+        && !state.returned()
+    {
+        status = execute_list(state, body, io)?;
+    }
+    Ok(status)
+}
+
+fn pipe_commands(state: &mut ShellState, pipeline: &Pipeline, io: &Io) -> Result<PipeCommands> {
+    let commands = pipeline
+        .commands
+        .iter()
+        .map(|command| stdio_command(state, command, io))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(PipeCommands {
+        commands,
+        text: format_pipeline(pipeline),
+    })
+}
+
+/// An and-or list run in a subshell, as a single command
+fn subshell_commands(and_or: &AndOr) -> PipeCommands {
+    let list = List {
+        items: vec![ListItem {
+            and_or: and_or.clone(),
+            background: false,
+            comments: vec![],
+            comment: None,
+        }],
+        comments: vec![],
+    };
+    let command = Command::Subshell(SubshellCommand::new(list));
+    PipeCommands {
+        commands: vec![StdioCommand::new(PIn::Empty, vec![], vec![], command)],
+        text: format_and_or(and_or),
+    }
+}
+
+fn stdio_command(state: &mut ShellState, command: &ast::Command, io: &Io) -> Result<StdioCommand> {
+    let (redirects, command) = match command {
+        ast::Command::Simple(simple) => {
+            let values = simple
+                .assignments
+                .iter()
+                .map(|assignment| &assignment.value);
+            let targets = simple.redirects.iter().map(Redirect::word);
+            check_unset(state, simple.words.iter().chain(values).chain(targets), io)?;
+            let mut args = expand_words(state, &simple.words);
+            let assignments = simple
+                .assignments
+                .iter()
+                .map(|assignment| {
+                    (
+                        assignment.name.clone(),
+                        expand_word(state, &assignment.value),
+                    )
+                })
+                .collect::<Vec<_>>();
+            if state.option(ShellOption::Xtrace) {
+                trace_command(state, &assignments, &args);
+            }
+            let command = match args.is_empty() {
+                true => Command::Assign(assignments),
+                false => {
+                    let cmd = args.remove(0);
+                    let args = CommandArgs(args);
+                    match BuiltinCommand::from_str(&cmd) {
+                        Ok(builtin) => Command::Builtin(builtin.with_args(args), assignments),
+                        Err(_) => match find_command(state, &cmd, &assignments) {
+                            Ok(path) => Command::Path(PathCommand {
+                                path,
+                                name: cmd,
+                                args,
+                                env: assignments,
+                                process_group: None,
+                                foreground: false,
+                            }),
+                            Err(error) => Command::Invalid(InvalidCommand(cmd, error)),
+                        },
+                    }
+                }
+            };
+            (&simple.redirects, command)
+        }
+        ast::Command::Compound {
+            body: CompoundCommand::Subshell(list),
+            redirects,
+        } => (
+            redirects,
+            Command::Subshell(SubshellCommand::new(list.clone())),
+        ),
+        ast::Command::Compound { body, redirects } => (redirects, Command::Compound(body.clone())),
+    };
+
+    let mut stdin = vec![];
+    let mut stdout = vec![];
+    let mut stderr = vec![];
+    for redirect in redirects {
+        match open_redirect(state, redirect)? {
+            PType::In(pin) => stdin.push(pin),
+            PType::Out(pout) => stdout.push(pout),
+            PType::Err(perr) => stderr.push(perr),
+        }
+    }
+    let stdin = stdin.pop().unwrap_or(PIn::Empty);
+
+    Ok(StdioCommand::new(stdin, stdout, stderr, command))
+}
+
+/// Executable run for `cmd`, searched in the `PATH` assigned for the command
+/// if there is one, without remembering it in the hash table
+fn find_command(
+    state: &mut ShellState,
+    cmd: &str,
+    assignments: &[(String, String)],
+) -> Result<PathBuf, ResolveError> {
+    match assignments.iter().rev().find(|(name, _)| name == "PATH") {
+        Some((_, path)) => resolve_command(cmd, Some(path)),
+        None => state.find_command(cmd),
+    }
+}
+
+/// With `nounset`, expanding an unset parameter fails the command, and exits
+/// the shell unless it is interactive
+fn check_unset<'a>(
+    state: &mut ShellState,
+    words: impl IntoIterator<Item = &'a Word>,
+    io: &Io,
+) -> Result<()> {
+    if !state.option(ShellOption::Nounset) {
+        return Ok(());
+    }
+    if let Some(name) = unset_parameter(state, words) {
+        if !state.interactive() {
+            io.write_stderr(format!("{name}: unbound variable\n").as_bytes())?;
+            exit_shell(state, 127);
+        }
+        bail!("{name}: unbound variable");
+    }
+    Ok(())
+}
+
+/// Print a simple command to stderr after expansion, prefixed by PS4
+fn trace_command(state: &ShellState, assignments: &[(String, String)], args: &[String]) {
+    let prefix = state.var("PS4").unwrap_or_else(|| "+ ".to_string());
+    let words = assignments
+        .iter()
+        .map(|(name, value)| format!("{name}={}", quote_word(value)))
+        .chain(args.iter().map(|arg| quote_word(arg)))
+        .collect::<Vec<_>>();
+    eprintln!("{prefix}{}", words.join(" "));
+}
+
+fn open_redirect(state: &ShellState, redirect: &Redirect) -> Result<PType> {
+    let word = expand_word(state, redirect.word());
+    match *redirect {
+        Redirect::Input { n, .. } => {
+            let file = File::open(&word).with_context(|| word.clone())?;
+            if n != 0 {
+                bail!("only support stdin for redirect input, received {n}")
+            }
+            Ok(PType::In(PIn::File(file)))
+        }
+        Redirect::Output { n, .. } => {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .append(false)
+                .write(true)
+                .open(&word)
+                .with_context(|| word.clone())?;
+            match n {
+                1 => Ok(PType::Out(POut::File(file))),
+                2 => Ok(PType::Err(PErr::File(file))),
+                _ => bail!("invalid file descriptor {n}"),
+            }
+        }
+        Redirect::AppendOutput { n, .. } => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&word)
+                .with_context(|| word.clone())?;
+            match n {
+                1 => Ok(PType::Out(POut::File(file))),
+                2 => Ok(PType::Err(PErr::File(file))),
+                _ => bail!("invalid file descriptor {n}"),
+            }
+        }
+    }
+}
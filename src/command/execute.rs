@@ -1,64 +1,52 @@
 use std::{
+    collections::BTreeMap,
     env, fs,
-    io::{BufRead, BufReader, Read},
-    process::{Child, Stdio},
+    io::{self, BufRead, Read, Write},
+    os::unix::process::CommandExt,
+    path::PathBuf,
     str::FromStr,
     thread::JoinHandle,
+    time::Duration,
 };
 
 use crate::{
-    io::{write_stderr, write_stdout, PErr, PIn, POut},
-    utils::path_lookup_exact,
+    io::{
+        child_output, copy_output, forked_output, write_stderr, write_stdout, Io, PErr, PIn, POut,
+    },
+    job::{claim_terminal, exit_interrupted, give_terminal, interrupted, reset_signals},
+    parse::is_name,
+    shell::exit_shell,
+    state::{ShellOption, ShellState, ShoptOption},
+    utils::{correct_spelling, find_file, resolve_command, ResolveError},
     HIST_FILE,
 };
 use anyhow::{Context, Result};
+use strum::IntoEnumIterator;
 
-use super::{BuiltinCommand, Command, CommandArgs, InvalidCommand, PathCommand};
+use super::{
+    conditional,
+    eval::{execute_compound, execute_input, execute_list},
+    expand::DEFAULT_IFS,
+    read::{self, ReadChar, ReadEnd, ReadOptions, TerminalMode},
+    wait_foreground, BuiltinCommand, Command, CommandArgs, InvalidCommand, PathCommand,
+    SubshellCommand,
+};
 
 #[derive(Debug)]
 pub(crate) enum MaybeBlockedCommand {
-    NonBlock,
+    /// Already finished with the given exit status
+    NonBlock(i32),
     Block {
-        stdout: JoinHandle<()>,
-        stderr: JoinHandle<()>,
-        child: Child,
+        pid: libc::pid_t,
+        /// Threads copying outputs that could not be given to the child directly
+        copies: Vec<JoinHandle<()>>,
     },
 }
 
-impl MaybeBlockedCommand {
-    pub fn kill(self) -> Result<()> {
-        match self {
-            MaybeBlockedCommand::NonBlock => {}
-            MaybeBlockedCommand::Block { mut child, .. } => {
-                child.kill()?;
-            }
-        }
-
-        Ok(())
-    }
-
-    pub fn wait(self) -> Result<()> {
-        match self {
-            MaybeBlockedCommand::NonBlock => {}
-            MaybeBlockedCommand::Block {
-                stdout,
-                stderr,
-                child,
-                ..
-            } => {
-                stdout.join().expect("cannot join stdout");
-                stderr.join().expect("cannot join stderr");
-                child.wait_with_output()?;
-            }
-        }
-
-        Ok(())
-    }
-}
-
 pub(super) trait Execute {
     fn execute(
         &mut self,
+        state: &mut ShellState,
         stdin: PIn,
         stdout: Vec<POut>,
         stderr: Vec<PErr>,
@@ -68,14 +56,50 @@ pub(super) trait Execute {
 impl Execute for Command {
     fn execute(
         &mut self,
+        state: &mut ShellState,
         stdin: PIn,
         stdout: Vec<POut>,
         stderr: Vec<PErr>,
     ) -> Result<MaybeBlockedCommand> {
         match self {
-            Command::Builtin(builtin_command) => builtin_command.execute(stdin, stdout, stderr),
-            Command::Invalid(invalid_command) => invalid_command.execute(stdin, stdout, stderr),
-            Command::Path(path_command) => path_command.execute(stdin, stdout, stderr),
+            Command::Builtin(builtin_command, env) => {
+                // assignments before a builtin only last for its execution
+                let saved: Vec<_> = env
+                    .iter()
+                    .map(|(name, _)| (name.clone(), state.var(name)))
+                    .collect();
+                for (name, value) in env.drain(..) {
+                    state.set_var(&name, value);
+                }
+                let result = builtin_command.execute(state, stdin, stdout, stderr);
+                for (name, value) in saved {
+                    match value {
+                        Some(value) => state.set_var(&name, value),
+                        None => state.unset_var(&name),
+                    }
+                }
+                result
+            }
+            Command::Invalid(invalid_command) => {
+                invalid_command.execute(state, stdin, stdout, stderr)
+            }
+            Command::Path(path_command) => path_command.execute(state, stdin, stdout, stderr),
+            Command::Subshell(subshell) => subshell.execute(state, stdin, stdout, stderr),
+            Command::Compound(compound) => {
+                let io = Io {
+                    stdin,
+                    stdout,
+                    stderr,
+                };
+                let status = execute_compound(state, compound, &io)?;
+                Ok(MaybeBlockedCommand::NonBlock(status))
+            }
+            Command::Assign(assignments) => {
+                for (name, value) in assignments.drain(..) {
+                    state.set_var(&name, value);
+                }
+                Ok(MaybeBlockedCommand::NonBlock(0))
+            }
         }
     }
 }
@@ -83,106 +107,235 @@ impl Execute for Command {
 impl Execute for InvalidCommand {
     fn execute(
         &mut self,
+        _: &mut ShellState,
         _: PIn,
         mut stdout: Vec<POut>,
-        _: Vec<PErr>,
+        mut stderr: Vec<PErr>,
     ) -> Result<MaybeBlockedCommand> {
-        write_stdout(
-            &mut stdout,
-            format!("{}: command not found\n", self.0).as_bytes(),
-        )?;
-
-        Ok(MaybeBlockedCommand::NonBlock)
+        let InvalidCommand(name, error) = self;
+        let message = format!("{name}: {error}\n");
+        match error {
+            ResolveError::NotFound => write_stdout(&mut stdout, message.as_bytes())?,
+            _ => write_stderr(&mut stderr, message.as_bytes())?,
+        }
+        Ok(MaybeBlockedCommand::NonBlock(error.status()))
     }
 }
 
 impl Execute for PathCommand {
     fn execute(
         &mut self,
-        mut stdin: PIn,
-        mut stdout: Vec<POut>,
-        mut stderr: Vec<PErr>,
+        state: &mut ShellState,
+        stdin: PIn,
+        stdout: Vec<POut>,
+        stderr: Vec<PErr>,
     ) -> Result<MaybeBlockedCommand> {
-        let executable = self
-            .path
-            .file_name()
-            .with_context(|| format!("invalid filename for path `{}`", self.path.display()))?;
-
-        let mut command = std::process::Command::new(executable);
-        let command = command.args(&self.args.0);
-        let mut child = command
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
-
-        let (cmd_stdin, cmd_stdout, cmd_stderr) = (
-            child.stdin.take().with_context(|| "cannot get stdin")?,
-            child.stdout.take().with_context(|| "cannot get stdout")?,
-            child.stderr.take().with_context(|| "cannot get stderr")?,
-        );
-
-        std::thread::spawn(move || {
-            stdin
-                .send_to_writer(cmd_stdin)
-                .expect("Failed to write to stdin");
-        });
-        let stdout = std::thread::spawn(move || {
-            let mut reader = BufReader::new(cmd_stdout);
-            let mut buf = [0; 1];
-            while reader.read_exact(&mut buf).is_ok() {
-                write_stdout(&mut stdout, &buf).expect("Failed to write to stdout");
-            }
-        });
-        let stderr = std::thread::spawn(move || {
-            let mut reader = BufReader::new(cmd_stderr);
-            let mut buf = [0; 1];
-            while reader.read_exact(&mut buf).is_ok() {
-                write_stderr(&mut stderr, &buf).expect("Failed to write to stderr");
+        let (stdout_stdio, stdout) = child_output(stdout)?;
+        // stderr is kept to report a failure to spawn
+        let child_stderr = stderr.iter().map(PErr::try_clone).collect::<Result<_>>()?;
+        let (stderr_stdio, child_stderr) = child_output(child_stderr)?;
+        // the resolved path is run, with the name as typed as argv[0]
+        let mut command = std::process::Command::new(&self.path);
+        let command = command
+            .arg0(&self.name)
+            .args(&self.args.0)
+            .env_clear()
+            .envs(state.exported_vars())
+            .envs(self.env.drain(..));
+        if let Some(pgid) = self.process_group {
+            command.process_group(pgid);
+            let foreground = self.foreground;
+            // SAFETY: only async-signal-safe functions are called
+            unsafe {
+                command.pre_exec(move || {
+                    if foreground {
+                        claim_terminal()?;
+                    }
+                    reset_signals()
+                })
+            };
+        }
+        let spawned = command
+            .stdin(stdin.into_stdio())
+            .stdout(stdout_stdio)
+            .stderr(stderr_stdio)
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            // the file changed since it was resolved, or its interpreter is missing
+            Err(err) => {
+                let error = match err.kind() {
+                    io::ErrorKind::PermissionDenied => ResolveError::PermissionDenied,
+                    io::ErrorKind::NotFound => ResolveError::NoSuchFile,
+                    _ => return Err(err.into()),
+                };
+                let mut invalid = InvalidCommand(self.name.clone(), error);
+                return invalid.execute(state, PIn::Empty, vec![], stderr);
             }
-        });
+        };
+        let pid = child.id() as libc::pid_t;
+        if let Some(pgid) = self.process_group {
+            // also done by the parent, as the group may be used before the child runs
+            let pgid = if pgid == 0 { pid } else { pgid };
+            // SAFETY: setpgid has no memory safety requirements
+            unsafe { libc::setpgid(pid, pgid) };
+        }
 
-        Ok(MaybeBlockedCommand::Block {
-            stdout,
-            stderr,
-            child,
-        })
+        let mut copies = vec![];
+        if let Some(outputs) = stdout {
+            let reader = child.stdout.take().with_context(|| "cannot get stdout")?;
+            copies.push(copy_output(reader, outputs));
+        }
+        if let Some(outputs) = child_stderr {
+            let reader = child.stderr.take().with_context(|| "cannot get stderr")?;
+            copies.push(copy_output(reader, outputs));
+        }
+
+        Ok(MaybeBlockedCommand::Block { pid, copies })
+    }
+}
+
+impl Execute for SubshellCommand {
+    fn execute(
+        &mut self,
+        state: &mut ShellState,
+        stdin: PIn,
+        stdout: Vec<POut>,
+        stderr: Vec<PErr>,
+    ) -> Result<MaybeBlockedCommand> {
+        let (stdout, copy) = forked_output(stdout)?;
+        // output buffered by the shell would be written by the child as well
+        io::stdout().flush()?;
+        // SAFETY: the child runs the commands with its copy of the shell and
+        // exits without returning
+        let pid = unsafe { libc::fork() };
+        match pid {
+            -1 => Err(io::Error::last_os_error().into()),
+            0 => {
+                if let Some(pgid) = self.process_group {
+                    // SAFETY: setpgid has no memory safety requirements
+                    unsafe { libc::setpgid(0, pgid) };
+                    if self.foreground {
+                        let _ = claim_terminal();
+                    }
+                    let _ = reset_signals();
+                }
+                // the subshell has neither job control nor a logout file,
+                // and leaves on errors as a script does
+                state.set_option(ShellOption::Monitor, false);
+                state.set_login(false);
+                state.set_interactive(false);
+                let io = Io {
+                    stdin,
+                    stdout,
+                    stderr,
+                };
+                let status = execute_list(state, &self.list, &io).unwrap_or_else(|err| {
+                    let _ = io.write_stderr(format!("{err:#}\n").as_bytes());
+                    1
+                });
+                if interrupted() {
+                    exit_interrupted();
+                }
+                std::process::exit(status)
+            }
+            pid => {
+                if let Some(pgid) = self.process_group {
+                    // also done by the child, as the group may be used before it runs
+                    let pgid = if pgid == 0 { pid } else { pgid };
+                    // SAFETY: setpgid has no memory safety requirements
+                    unsafe { libc::setpgid(pid, pgid) };
+                }
+                Ok(MaybeBlockedCommand::Block {
+                    pid,
+                    copies: copy.into_iter().collect(),
+                })
+            }
+        }
     }
 }
 
 impl Execute for BuiltinCommand {
     fn execute(
         &mut self,
-        _: PIn,
+        state: &mut ShellState,
+        stdin: PIn,
         stdout: Vec<POut>,
         stderr: Vec<PErr>,
     ) -> Result<MaybeBlockedCommand> {
         match self {
-            BuiltinCommand::Exit(args) => exit_command(args, stderr),
+            BuiltinCommand::Exit(args) => exit_command(args, state, stderr),
             BuiltinCommand::Echo(args) => echo_command(args, stdout),
-            BuiltinCommand::Type(args) => type_command(args, stdout),
+            BuiltinCommand::Type(args) => type_command(args, state, stdout),
             BuiltinCommand::Pwd => pwd_command(stdout),
-            BuiltinCommand::Cd(args) => cd_command(args, stderr),
+            BuiltinCommand::Cd(args) => cd_command(args, state, stdout, stderr),
             BuiltinCommand::History(args) => history_command(args, stdout, stderr),
+            BuiltinCommand::Alias(args) => alias_command(args, state, stdout, stderr),
+            BuiltinCommand::Unalias(args) => unalias_command(args, state, stderr),
+            BuiltinCommand::Test(args) => test_command("test", &args.0, stderr),
+            BuiltinCommand::Bracket(args) => match args.0.split_last() {
+                Some((last, args)) if last == "]" => test_command("[", args, stderr),
+                _ => {
+                    let mut stderr = stderr;
+                    write_stderr(&mut stderr, b"[: missing `]'\n")?;
+                    Ok(MaybeBlockedCommand::NonBlock(2))
+                }
+            },
+            BuiltinCommand::Set(args) => set_command(args, state, stdout, stderr),
+            BuiltinCommand::Read(args) => read_command(args, state, stdin, stderr),
+            BuiltinCommand::Jobs(args) => jobs_command(args, state, stdout, stderr),
+            BuiltinCommand::Fg(args) => fg_command(args, state, stdout, stderr),
+            BuiltinCommand::Bg(args) => bg_command(args, state, stdout, stderr),
+            BuiltinCommand::Hash(args) => hash_command(args, state, stdout, stderr),
+            BuiltinCommand::Shift(args) => shift_command(args, state, stderr),
+            BuiltinCommand::Return(args) => return_command(args, state, stderr),
+            BuiltinCommand::Export(args) => export_command(args, state, stdout, stderr),
+            BuiltinCommand::Unset(args) => unset_command(args, state, stderr),
+            BuiltinCommand::Shopt(args) => shopt_command(args, state, stdout, stderr),
+            BuiltinCommand::Source(args) => source_command(
+                "source",
+                args,
+                state,
+                Io {
+                    stdin,
+                    stdout,
+                    stderr,
+                },
+            ),
+            BuiltinCommand::Dot(args) => source_command(
+                ".",
+                args,
+                state,
+                Io {
+                    stdin,
+                    stdout,
+                    stderr,
+                },
+            ),
         }
     }
 }
 
-fn exit_command(args: &mut CommandArgs, mut stderr: Vec<PErr>) -> Result<MaybeBlockedCommand> {
-    match args.0.first_mut() {
+fn exit_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let status = match args.0.first() {
         Some(code) => match code.parse::<i32>() {
-            Ok(code) => std::process::exit(code),
+            Ok(code) => code,
             Err(_) => {
                 write_stderr(
                     &mut stderr,
                     format!("invalid args: [{}]", args.0.join(",")).as_bytes(),
                 )?;
-                std::process::exit(-1);
+                -1
             }
         },
-        // no args given
-        None => std::process::exit(0),
-    }
+        // without arguments the status is the one of the last command
+        None => state.status(),
+    };
+    exit_shell(state, status)
 }
 
 fn echo_command(args: &mut CommandArgs, mut stdout: Vec<POut>) -> Result<MaybeBlockedCommand> {
@@ -195,27 +348,227 @@ fn echo_command(args: &mut CommandArgs, mut stdout: Vec<POut>) -> Result<MaybeBl
     }
     write_stdout(&mut stdout, b"\n")?;
 
-    Ok(MaybeBlockedCommand::NonBlock)
+    Ok(MaybeBlockedCommand::NonBlock(0))
 }
 
-fn type_command(args: &mut CommandArgs, mut stdout: Vec<POut>) -> Result<MaybeBlockedCommand> {
+fn type_command(
+    args: &mut CommandArgs,
+    state: &ShellState,
+    mut stdout: Vec<POut>,
+) -> Result<MaybeBlockedCommand> {
+    let mut status = 0;
     for arg in &args.0 {
-        match BuiltinCommand::from_str(arg) {
-            Ok(_) => write_stdout(
+        match (BuiltinCommand::from_str(arg), state.hashed_path(arg)) {
+            (Ok(_), _) => write_stdout(
                 &mut stdout,
                 format!("{arg} is a shell builtin\n").as_bytes(),
             )?,
-            Err(_) => match path_lookup_exact(arg) {
+            (Err(_), Some(path)) => write_stdout(
+                &mut stdout,
+                format!("{arg} is hashed ({})\n", path.display()).as_bytes(),
+            )?,
+            (Err(_), None) => match resolve_command(arg, state.var("PATH").as_deref()) {
                 Ok(path) => write_stdout(
                     &mut stdout,
                     format!("{arg} is {}\n", path.as_path().display()).as_bytes(),
                 )?,
-                Err(_) => write_stdout(&mut stdout, format!("{arg}: not found\n").as_bytes())?,
+                Err(_) => {
+                    write_stdout(&mut stdout, format!("{arg}: not found\n").as_bytes())?;
+                    status = 1;
+                }
             },
         }
     }
 
-    Ok(MaybeBlockedCommand::NonBlock)
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+/// Options of the `hash` builtin
+#[derive(Default)]
+struct HashOptions {
+    /// `-r`, forget every location
+    reset: bool,
+    /// `-d`, forget the locations of the names
+    delete: bool,
+    /// `-t`, print the locations of the names
+    print: bool,
+    /// `-l`, list the table as commands that recreate it
+    reusable: bool,
+    /// `-p`, location given to the names instead of searching PATH
+    path: Option<PathBuf>,
+    names: Vec<String>,
+}
+
+impl HashOptions {
+    fn parse(args: &[String]) -> Result<HashOptions, String> {
+        let mut options = HashOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let flags = match arg.strip_prefix('-') {
+                Some("-") => break,
+                Some(flags) if !flags.is_empty() => flags,
+                _ => {
+                    options.names.push(arg.clone());
+                    break;
+                }
+            };
+            let mut chars = flags.chars();
+            while let Some(flag) = chars.next() {
+                match flag {
+                    'r' => options.reset = true,
+                    'd' => options.delete = true,
+                    't' => options.print = true,
+                    'l' => options.reusable = true,
+                    'p' => {
+                        let path = match chars.as_str() {
+                            "" => args
+                                .next()
+                                .cloned()
+                                .ok_or("-p: option requires an argument")?,
+                            attached => attached.to_string(),
+                        };
+                        options.path = Some(path.into());
+                        break;
+                    }
+                    flag => return Err(format!("-{flag}: invalid option")),
+                }
+            }
+        }
+        options.names.extend(args.cloned());
+        Ok(options)
+    }
+}
+
+/// Show or change the remembered locations of commands
+fn hash_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stdout: Vec<POut>,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let options = match HashOptions::parse(&args.0) {
+        Ok(options) => options,
+        Err(err) => {
+            write_stderr(&mut stderr, format!("hash: {err}\n").as_bytes())?;
+            return Ok(MaybeBlockedCommand::NonBlock(2));
+        }
+    };
+    if options.reset {
+        state.clear_hashed();
+    }
+
+    if options.names.is_empty() {
+        if options.reset || options.delete || options.print || options.path.is_some() {
+            return Ok(MaybeBlockedCommand::NonBlock(0));
+        }
+        let mut output = String::new();
+        for (name, hashed) in state.hashed() {
+            match options.reusable {
+                true => output += &format!("builtin hash -p {} {name}\n", hashed.path.display()),
+                false => output += &format!("{:4}\t{}\n", hashed.hits, hashed.path.display()),
+            }
+        }
+        let output = match output.is_empty() {
+            true => "hash: hash table empty\n".to_string(),
+            false if options.reusable => output,
+            false => format!("hits\tcommand\n{output}"),
+        };
+        write_stdout(&mut stdout, output.as_bytes())?;
+        return Ok(MaybeBlockedCommand::NonBlock(0));
+    }
+
+    let mut status = 0;
+    for name in &options.names {
+        let found = if options.delete {
+            state.unhash(name)
+        } else if options.print {
+            let path = state
+                .hashed_path(name)
+                .map(|path| path.display().to_string());
+            if let Some(path) = &path {
+                let line = match options.names.len() {
+                    1 => format!("{path}\n"),
+                    _ => format!("{name}\t{path}\n"),
+                };
+                write_stdout(&mut stdout, line.as_bytes())?;
+            }
+            path.is_some()
+        } else if let Some(path) = &options.path {
+            state.hash(name, path.clone());
+            true
+        } else if BuiltinCommand::from_str(name).is_ok() {
+            // builtins are not searched in PATH
+            true
+        } else {
+            match resolve_command(name, state.var("PATH").as_deref()) {
+                Ok(path) => {
+                    state.hash(name, path);
+                    true
+                }
+                Err(_) => false,
+            }
+        };
+        if !found {
+            write_stderr(&mut stderr, format!("hash: {name}: not found\n").as_bytes())?;
+            status = 1;
+        }
+    }
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+/// Drop positional parameters, one unless a count is given
+fn shift_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let count = match args.0.first() {
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                write_stderr(
+                    &mut stderr,
+                    format!("shift: {count}: numeric argument required\n").as_bytes(),
+                )?;
+                return Ok(MaybeBlockedCommand::NonBlock(1));
+            }
+        },
+        None => 1,
+    };
+    // shifting more than there are parameters fails without a message
+    Ok(MaybeBlockedCommand::NonBlock(i32::from(
+        !state.shift(count),
+    )))
+}
+
+/// Stop the sourced file, with the given status or the one of the last command
+fn return_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    if !state.in_source() {
+        write_stderr(
+            &mut stderr,
+            b"return: can only `return' from a function or sourced script\n",
+        )?;
+        return Ok(MaybeBlockedCommand::NonBlock(1));
+    }
+    let status = match args.0.first() {
+        Some(status) => match status.parse::<i32>() {
+            Ok(status) => status & 0xff,
+            Err(_) => {
+                write_stderr(
+                    &mut stderr,
+                    format!("return: {status}: numeric argument required\n").as_bytes(),
+                )?;
+                2
+            }
+        },
+        None => state.status(),
+    };
+    state.set_returned(status);
+    Ok(MaybeBlockedCommand::NonBlock(status))
 }
 
 fn pwd_command(mut stdout: Vec<POut>) -> Result<MaybeBlockedCommand> {
@@ -225,24 +578,42 @@ fn pwd_command(mut stdout: Vec<POut>) -> Result<MaybeBlockedCommand> {
         format!("{}\n", current_dir.as_path().display()).as_bytes(),
     )?;
 
-    Ok(MaybeBlockedCommand::NonBlock)
+    Ok(MaybeBlockedCommand::NonBlock(0))
 }
 
-fn cd_command(args: &mut CommandArgs, mut stderr: Vec<PErr>) -> Result<MaybeBlockedCommand> {
+fn cd_command(
+    args: &mut CommandArgs,
+    state: &ShellState,
+    mut stdout: Vec<POut>,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
     match &args.0[..] {
         [path] => {
-            let expanded_path = shellexpand::tilde(&path);
-            if std::env::set_current_dir(expanded_path.as_ref()).is_err() {
+            if std::env::set_current_dir(path).is_err() {
+                // with cdspell the corrected directory is printed
+                let corrected = state
+                    .shopt(ShoptOption::Cdspell)
+                    .then(|| correct_spelling(path))
+                    .flatten()
+                    .filter(|dir| std::env::set_current_dir(dir).is_ok());
+                if let Some(dir) = corrected {
+                    write_stdout(&mut stdout, format!("{}\n", dir.display()).as_bytes())?;
+                    return Ok(MaybeBlockedCommand::NonBlock(0));
+                }
                 write_stderr(
                     &mut stderr,
                     format!("cd: {path}: No such file or directory\n").as_bytes(),
                 )?;
+                return Ok(MaybeBlockedCommand::NonBlock(1));
             }
         }
-        _ => write_stderr(&mut stderr, "cd: No path given".as_bytes())?,
+        _ => {
+            write_stderr(&mut stderr, "cd: No path given".as_bytes())?;
+            return Ok(MaybeBlockedCommand::NonBlock(1));
+        }
     }
 
-    Ok(MaybeBlockedCommand::NonBlock)
+    Ok(MaybeBlockedCommand::NonBlock(0))
 }
 
 fn history_command(
@@ -265,6 +636,7 @@ fn history_command(
                     &mut stderr,
                     format!("invalid limiting entries, not a number: {n}\n").as_bytes(),
                 )?;
+                return Ok(MaybeBlockedCommand::NonBlock(1));
             }
         }
     }
@@ -274,5 +646,605 @@ fn history_command(
         let line = line?;
         write_stdout(&mut stdout, format!("    {id} {line}\n").as_bytes())?;
     }
-    Ok(MaybeBlockedCommand::NonBlock)
+    Ok(MaybeBlockedCommand::NonBlock(0))
+}
+
+/// Status 0 if the expression is true, 1 if false and 2 if it is invalid
+fn test_command(name: &str, args: &[String], mut stderr: Vec<PErr>) -> Result<MaybeBlockedCommand> {
+    let status = match conditional::evaluate(args) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            write_stderr(&mut stderr, format!("{name}: {err}\n").as_bytes())?;
+            2
+        }
+    };
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+fn alias_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stdout: Vec<POut>,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let print = |name: &str, value: &str| format!("alias {name}={}\n", single_quote(value));
+
+    if args.0.is_empty() {
+        for (name, value) in state.aliases() {
+            write_stdout(&mut stdout, print(name, value).as_bytes())?;
+        }
+        return Ok(MaybeBlockedCommand::NonBlock(0));
+    }
+
+    let mut status = 0;
+    for arg in &args.0 {
+        match arg.split_once('=') {
+            Some((name, value)) => {
+                state
+                    .aliases_mut()
+                    .insert(name.to_string(), value.to_string());
+            }
+            None => match state.aliases().get(arg) {
+                Some(value) => write_stdout(&mut stdout, print(arg, value).as_bytes())?,
+                None => {
+                    write_stderr(&mut stderr, format!("alias: {arg}: not found\n").as_bytes())?;
+                    status = 1;
+                }
+            },
+        }
+    }
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+fn unalias_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    if args.0.first().is_some_and(|arg| arg == "-a") {
+        state.aliases_mut().clear();
+        return Ok(MaybeBlockedCommand::NonBlock(0));
+    }
+
+    let mut status = 0;
+    for name in &args.0 {
+        if state.aliases_mut().remove(name).is_none() {
+            write_stderr(
+                &mut stderr,
+                format!("unalias: {name}: not found\n").as_bytes(),
+            )?;
+            status = 1;
+        }
+    }
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+/// Mark variables as exported, assigning them with `name=value`. Without
+/// names the exported variables are listed, `-n` removes the attribute.
+fn export_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stdout: Vec<POut>,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let (flags, names): (Vec<_>, Vec<_>) = args.0.iter().partition(|arg| arg.starts_with('-'));
+    let mut unexport = false;
+    for flag in flags.iter().flat_map(|flag| flag.chars().skip(1)) {
+        match flag {
+            'n' => unexport = true,
+            'p' => {}
+            flag => {
+                write_stderr(
+                    &mut stderr,
+                    format!("export: -{flag}: invalid option\n").as_bytes(),
+                )?;
+                return Ok(MaybeBlockedCommand::NonBlock(2));
+            }
+        }
+    }
+
+    if names.is_empty() {
+        for (name, value) in state.exported_vars() {
+            let line = format!("export {name}={}\n", quote_word(value));
+            write_stdout(&mut stdout, line.as_bytes())?;
+        }
+        return Ok(MaybeBlockedCommand::NonBlock(0));
+    }
+
+    let mut status = 0;
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_name(name) {
+            write_stderr(
+                &mut stderr,
+                format!("export: `{arg}': not a valid identifier\n").as_bytes(),
+            )?;
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            state.set_var(name, value.to_string());
+        }
+        match unexport {
+            true => state.unexport(name),
+            false => state.export(name),
+        }
+    }
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+/// Remove variables, `-f` is accepted for functions but there are none
+fn unset_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let (flags, names): (Vec<_>, Vec<_>) = args.0.iter().partition(|arg| arg.starts_with('-'));
+    let mut functions = false;
+    for flag in flags.iter().flat_map(|flag| flag.chars().skip(1)) {
+        match flag {
+            'v' => functions = false,
+            'f' => functions = true,
+            flag => {
+                write_stderr(
+                    &mut stderr,
+                    format!("unset: -{flag}: invalid option\n").as_bytes(),
+                )?;
+                return Ok(MaybeBlockedCommand::NonBlock(2));
+            }
+        }
+    }
+    if functions {
+        return Ok(MaybeBlockedCommand::NonBlock(0));
+    }
+
+    let mut status = 0;
+    for name in names {
+        if !is_name(name) {
+            write_stderr(
+                &mut stderr,
+                format!("unset: `{name}': not a valid identifier\n").as_bytes(),
+            )?;
+            status = 1;
+            continue;
+        }
+        state.unset_var(name);
+    }
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+/// Turn options on with `-o name` and off with `+o name`, without a name
+/// print every option
+fn set_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stdout: Vec<POut>,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    // without arguments every variable is listed, sorted by name
+    if args.0.is_empty() {
+        let mut values = state
+            .vars()
+            .into_iter()
+            .map(|(name, value)| (name, quote_word(value)))
+            .collect::<BTreeMap<_, _>>();
+        for (name, array) in state.arrays() {
+            let elements = array
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("[{i}]={}", quote_word(value)))
+                .collect::<Vec<_>>();
+            values.insert(name, format!("({})", elements.join(" ")));
+        }
+        for (name, value) in values {
+            write_stdout(&mut stdout, format!("{name}={value}\n").as_bytes())?;
+        }
+        return Ok(MaybeBlockedCommand::NonBlock(0));
+    }
+
+    let mut status = 0;
+    let mut positional = None;
+    let mut args = args.0.iter();
+    while let Some(arg) = args.next() {
+        let (sign, flags) = match arg.as_str() {
+            // the rest are the positional parameters, none after `--`
+            "--" => {
+                positional = Some(args.by_ref().cloned().collect());
+                break;
+            }
+            "-" => {
+                let rest = args.by_ref().cloned().collect::<Vec<_>>();
+                positional = (!rest.is_empty()).then_some(rest);
+                break;
+            }
+            flags if flags.len() > 1 && (flags.starts_with('-') || flags.starts_with('+')) => {
+                flags.split_at(1)
+            }
+            _ => {
+                positional = Some(std::iter::once(arg).chain(args).cloned().collect());
+                break;
+            }
+        };
+        let enabled = sign == "-";
+        for flag in flags.chars() {
+            let option = match flag {
+                // `-o` without a name lists the options
+                'o' => match args.next() {
+                    Some(name) => ShellOption::from_str(name).map_err(|_| name.clone()),
+                    None => {
+                        print_options(state, enabled, &mut stdout)?;
+                        continue;
+                    }
+                },
+                flag => ShellOption::from_letter(flag).ok_or_else(|| format!("{sign}{flag}")),
+            };
+            match option {
+                Ok(option) => state.set_option(option, enabled),
+                Err(name) => {
+                    let message = match flag {
+                        'o' => format!("set: {name}: invalid option name\n"),
+                        _ => format!("set: {name}: invalid option\n"),
+                    };
+                    write_stderr(&mut stderr, message.as_bytes())?;
+                    status = 2;
+                }
+            }
+        }
+    }
+    if let Some(positional) = positional {
+        state.set_positional(positional);
+    }
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+/// Print the state of every option, `set -o` is meant for reading and
+/// `set +o` for executing again
+fn print_options(state: &ShellState, readable: bool, stdout: &mut [POut]) -> Result<()> {
+    for option in ShellOption::iter() {
+        let (name, on) = (option.as_ref(), state.option(option));
+        let line = match readable {
+            true => format!("{name:<15}\t{}\n", if on { "on" } else { "off" }),
+            false => format!("set {}o {name}\n", if on { '-' } else { '+' }),
+        };
+        write_stdout(stdout, line.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Set (`-s`) or unset (`-u`) the named options, otherwise print their state.
+/// The status of a query is 1 if one of them is off.
+fn shopt_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stdout: Vec<POut>,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let (flags, names): (Vec<_>, Vec<_>) = args.0.iter().partition(|arg| arg.starts_with('-'));
+    let (mut set, mut unset, mut quiet, mut print) = (false, false, false, false);
+    for flag in flags.iter().flat_map(|flag| flag.chars().skip(1)) {
+        match flag {
+            's' => set = true,
+            'u' => unset = true,
+            'q' => quiet = true,
+            'p' => print = true,
+            flag => {
+                write_stderr(
+                    &mut stderr,
+                    format!("shopt: -{flag}: invalid option\n").as_bytes(),
+                )?;
+                return Ok(MaybeBlockedCommand::NonBlock(2));
+            }
+        }
+    }
+    if set && unset {
+        write_stderr(
+            &mut stderr,
+            b"shopt: cannot set and unset shell options simultaneously\n",
+        )?;
+        return Ok(MaybeBlockedCommand::NonBlock(1));
+    }
+
+    let mut status = 0;
+    let mut options = vec![];
+    for name in &names {
+        match ShoptOption::from_str(name) {
+            Ok(option) => options.push(option),
+            Err(_) => {
+                write_stderr(
+                    &mut stderr,
+                    format!("shopt: {name}: invalid shell option name\n").as_bytes(),
+                )?;
+                status = 1;
+            }
+        }
+    }
+    if (set || unset) && !names.is_empty() {
+        for option in options {
+            state.set_shopt(option, set);
+        }
+        return Ok(MaybeBlockedCommand::NonBlock(status));
+    }
+
+    // without names every option is listed, only those on with `-s` or off with `-u`
+    if names.is_empty() {
+        options = ShoptOption::iter()
+            .filter(|&option| !(set || unset) || state.shopt(option) == set)
+            .collect();
+    }
+    for option in options {
+        let (name, on) = (option.as_ref(), state.shopt(option));
+        if !on && !names.is_empty() {
+            status = 1;
+        }
+        if quiet {
+            continue;
+        }
+        let line = match print {
+            true => format!("shopt {} {name}\n", if on { "-s" } else { "-u" }),
+            false => format!("{name:<15}\t{}\n", if on { "on" } else { "off" }),
+        };
+        write_stdout(&mut stdout, line.as_bytes())?;
+    }
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+/// Status of `read` when the timeout expires, as if killed by SIGALRM
+const READ_TIMEOUT_STATUS: i32 = 128 + libc::SIGALRM;
+
+/// Read a line and assign its fields to the names, `REPLY` gets the whole
+/// line if there is none
+fn read_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stdin: PIn,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let options = match ReadOptions::parse(&args.0) {
+        Ok(options) => options,
+        Err(err) => {
+            write_stderr(&mut stderr, format!("read: {err}\n").as_bytes())?;
+            return Ok(MaybeBlockedCommand::NonBlock(2));
+        }
+    };
+    let mut names = options.names.iter().chain(&options.array);
+    if let Some(name) = names.find(|name| !is_name(name)) {
+        write_stderr(
+            &mut stderr,
+            format!("read: `{name}': not a valid identifier\n").as_bytes(),
+        )?;
+        return Ok(MaybeBlockedCommand::NonBlock(1));
+    }
+
+    // `-t 0` only tells whether there is input to read
+    if options.timeout.is_some_and(|timeout| timeout.is_zero()) {
+        let ready = stdin.poll(Duration::ZERO)?;
+        return Ok(MaybeBlockedCommand::NonBlock(i32::from(!ready)));
+    }
+
+    let terminal = stdin.is_terminal();
+    if let Some(prompt) = options.prompt.as_ref().filter(|_| terminal) {
+        write_stderr(&mut stderr, prompt.as_bytes())?;
+    }
+    // `-n` and `-d` end the input before a newline the terminal waits for
+    let unbuffered = options.count.is_some() || options.delimiter != b'\n';
+    let mode = match options.silent || unbuffered {
+        true => stdin
+            .as_raw_fd()
+            .and_then(|fd| TerminalMode::new(fd, options.silent, unbuffered)),
+        false => None,
+    };
+    let (input, end) = read::read_input(&mut stdin, &options)?;
+    drop(mode);
+
+    let chars: Vec<ReadChar> = match options.raw {
+        true => input.chars().map(|c| (c, false)).collect(),
+        false => read::unescape(&input),
+    };
+    let ifs = state.var("IFS").unwrap_or_else(|| DEFAULT_IFS.to_string());
+    if let Some(array) = &options.array {
+        state.set_array(array, read::split_line(&chars, &ifs, None));
+    } else if options.names.is_empty() {
+        state.set_var("REPLY", chars.iter().map(|&(c, _)| c).collect());
+    } else {
+        let values = read::split_line(&chars, &ifs, Some(options.names.len()));
+        for (name, value) in options.names.iter().zip(values) {
+            state.set_var(name, value);
+        }
+    }
+
+    // the end of the input is a failure even if a partial line was read
+    let status = match end {
+        ReadEnd::Complete => 0,
+        ReadEnd::Eof => 1,
+        ReadEnd::Timeout => READ_TIMEOUT_STATUS,
+    };
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+/// Execute the commands of a file in the current shell.
+///
+/// A name without a slash is searched in PATH, then in the current directory.
+/// Arguments after the name are the positional parameters while the file runs.
+/// `/dev/stdin` is the input of the builtin, so that `cmd | source /dev/stdin`
+/// runs the output of `cmd`.
+fn source_command(
+    name: &str,
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut io: Io,
+) -> Result<MaybeBlockedCommand> {
+    let Some(path) = args.0.first() else {
+        io.write_stderr(format!("{name}: filename argument required\n").as_bytes())?;
+        return Ok(MaybeBlockedCommand::NonBlock(2));
+    };
+
+    let contents = match path.as_str() {
+        "/dev/stdin" | "/dev/fd/0" | "/proc/self/fd/0" => io.stdin.read_to_end()?,
+        path => match fs::read(find_file(path, state.var("PATH").as_deref())) {
+            Ok(contents) => contents,
+            Err(_) => {
+                io.write_stderr(format!("{name}: {path}: No such file or directory\n").as_bytes())?;
+                return Ok(MaybeBlockedCommand::NonBlock(1));
+            }
+        },
+    };
+
+    // the arguments replace the positional parameters until the file ends
+    let positional = (args.0.len() > 1).then(|| {
+        let saved = state.positional().to_vec();
+        state.set_positional(args.0[1..].to_vec());
+        saved
+    });
+    state.enter_source();
+    let result = execute_input(state, &String::from_utf8_lossy(&contents), &io);
+    let returned = state.leave_source();
+    if let Some(positional) = positional {
+        state.set_positional(positional);
+    }
+    let status = match result? {
+        Ok(status) => returned.unwrap_or(status),
+        Err((line, err)) => {
+            io.write_stderr(format!("{name}: {path}: line {line}: {err}\n").as_bytes())?;
+            2
+        }
+    };
+    Ok(MaybeBlockedCommand::NonBlock(status))
+}
+
+/// List jobs with their state, `-l` adds their pid and `-p` only prints it
+fn jobs_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stdout: Vec<POut>,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let (flags, specs): (Vec<_>, Vec<_>) = args.0.iter().partition(|arg| arg.starts_with('-'));
+    let (mut long, mut pids) = (false, false);
+    for flag in flags.iter().flat_map(|flag| flag.chars().skip(1)) {
+        match flag {
+            'l' => long = true,
+            'p' => pids = true,
+            flag => {
+                write_stderr(
+                    &mut stderr,
+                    format!("jobs: -{flag}: invalid option\n").as_bytes(),
+                )?;
+                return Ok(MaybeBlockedCommand::NonBlock(2));
+            }
+        }
+    }
+
+    state.jobs_mut().poll()?;
+    let ids = match specs.is_empty() {
+        true => state.jobs().jobs().iter().map(|job| job.id).collect(),
+        false => {
+            let mut ids = vec![];
+            for spec in specs {
+                match state.jobs().find(Some(spec)) {
+                    Ok(id) => ids.push(id),
+                    Err(err) => {
+                        write_stderr(&mut stderr, format!("jobs: {err}\n").as_bytes())?;
+                        return Ok(MaybeBlockedCommand::NonBlock(1));
+                    }
+                }
+            }
+            ids
+        }
+    };
+
+    let jobs = state.jobs();
+    for job in jobs.jobs().iter().filter(|job| ids.contains(&job.id)) {
+        let line = match pids {
+            true => job.pgid.or(job.last_pid()).unwrap_or_default().to_string(),
+            false => job.describe(jobs.mark(job.id), long),
+        };
+        write_stdout(&mut stdout, format!("{line}\n").as_bytes())?;
+    }
+    // jobs that are done have been reported
+    state.jobs_mut().remove_done();
+    Ok(MaybeBlockedCommand::NonBlock(0))
+}
+
+/// Continue a job in the foreground and wait for it
+fn fg_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stdout: Vec<POut>,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let id = match find_job("fg", args, state) {
+        Ok(id) => id,
+        Err(err) => {
+            write_stderr(&mut stderr, format!("{err}\n").as_bytes())?;
+            return Ok(MaybeBlockedCommand::NonBlock(1));
+        }
+    };
+    let Some(mut job) = state.jobs_mut().remove(id) else {
+        return Ok(MaybeBlockedCommand::NonBlock(1));
+    };
+
+    write_stdout(&mut stdout, format!("{}\n", job.text).as_bytes())?;
+    if let Some(pgid) = job.pgid {
+        give_terminal(pgid)?;
+    }
+    job.resume()?;
+    let statuses = wait_foreground(state, job, &mut stderr)?;
+    Ok(MaybeBlockedCommand::NonBlock(
+        statuses.last().copied().unwrap_or_default(),
+    ))
+}
+
+/// Continue a stopped job in the background
+fn bg_command(
+    args: &mut CommandArgs,
+    state: &mut ShellState,
+    mut stdout: Vec<POut>,
+    mut stderr: Vec<PErr>,
+) -> Result<MaybeBlockedCommand> {
+    let id = match find_job("bg", args, state) {
+        Ok(id) => id,
+        Err(err) => {
+            write_stderr(&mut stderr, format!("{err}\n").as_bytes())?;
+            return Ok(MaybeBlockedCommand::NonBlock(1));
+        }
+    };
+    let mark = state.jobs().mark(id);
+    if let Some(job) = state.jobs_mut().get_mut(id) {
+        job.resume()?;
+        write_stdout(
+            &mut stdout,
+            format!("[{id}]{mark} {} &\n", job.text).as_bytes(),
+        )?;
+    }
+    Ok(MaybeBlockedCommand::NonBlock(0))
+}
+
+/// Job given to `fg` or `bg`, which need job control
+fn find_job(name: &str, args: &CommandArgs, state: &ShellState) -> Result<usize, String> {
+    if !state.option(ShellOption::Monitor) {
+        return Err(format!("{name}: no job control"));
+    }
+    state
+        .jobs()
+        .find(args.0.first().map(String::as_str))
+        .map_err(|err| format!("{name}: {err}"))
+}
+
+/// Quote a word only if the shell would split or expand it, so that it can be
+/// read back
+pub(super) fn quote_word(word: &str) -> String {
+    let special = |c: char| c.is_whitespace() || "'\"\\$`|&;<>()*?[]{}~#!".contains(c);
+    match word.is_empty() || word.contains(special) {
+        true => single_quote(word),
+        false => word.to_string(),
+    }
+}
+
+/// Quote a value so that the shell reads it back unchanged
+fn single_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
 }
@@ -0,0 +1,351 @@
+use crate::{
+    ast::{Word, WordPart},
+    state::{ShellOption, ShellState, ShoptOption},
+};
+
+/// Field separators used when `IFS` is unset
+pub(super) const DEFAULT_IFS: &str = " \t\n";
+
+/// Expand parameters and a leading tilde, then remove quotes.
+///
+/// Used where a single word is expected, so there is neither field splitting
+/// nor pathname expansion.
+pub(crate) fn expand_word(state: &ShellState, word: &Word) -> String {
+    let mut expanded = String::new();
+    for (i, part) in word.0.iter().enumerate() {
+        match part {
+            WordPart::Literal(s) if i == 0 => expanded += &shellexpand::tilde(s),
+            part => expand_part(state, part, &mut expanded, str::to_string),
+        }
+    }
+    expanded
+}
+
+/// Expand words into fields, in the POSIX order: tilde and parameter
+/// expansion, field splitting, pathname expansion and quote removal
+pub(crate) fn expand_words(state: &ShellState, words: &[Word]) -> Vec<String> {
+    let ifs = state.var("IFS").unwrap_or_else(|| DEFAULT_IFS.to_string());
+    words
+        .iter()
+        .flat_map(|word| split_word(state, word, &ifs))
+        .flat_map(|field| match state.option(ShellOption::Noglob) {
+            true => vec![field.text],
+            false => field.expand_pathname(state),
+        })
+        .collect()
+}
+
+/// First parameter of the words that is not set, an error with `nounset`
+pub(crate) fn unset_parameter<'a>(
+    state: &ShellState,
+    words: impl IntoIterator<Item = &'a Word>,
+) -> Option<&'a str> {
+    fn find<'a>(state: &ShellState, parts: &'a [WordPart]) -> Option<&'a str> {
+        parts.iter().find_map(|part| match part {
+            WordPart::DoubleQuoted(parts) => find(state, parts),
+            WordPart::Parameter(name) if !is_set(state, name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+    words.into_iter().find_map(|word| find(state, &word.0))
+}
+
+fn is_set(state: &ShellState, name: &str) -> bool {
+    if let Some((name, subscript)) = name.split_once('[') {
+        let array = state.array(name);
+        return match subscript.trim_end_matches(']') {
+            "@" | "*" => true,
+            index => index
+                .parse::<usize>()
+                .is_ok_and(|index| index < array.len()),
+        };
+    }
+    match name {
+        "0" | "#" | "@" | "*" | "$" | "?" | "-" => true,
+        "!" => state.background_pid().is_some(),
+        name if name.bytes().all(|b| b.is_ascii_digit()) => name
+            .parse::<usize>()
+            .is_ok_and(|n| n <= state.positional().len()),
+        name => state.var(name).is_some(),
+    }
+}
+
+/// Expand a word used as a glob pattern, quoted characters only match themselves
+pub(crate) fn expand_pattern(state: &ShellState, word: &Word) -> String {
+    let mut expanded = String::new();
+    for part in &word.0 {
+        expand_part(state, part, &mut expanded, glob::Pattern::escape);
+    }
+    expanded
+}
+
+/// Expand the right side of `=~`, quoted characters only match themselves
+pub(crate) fn expand_regex(state: &ShellState, word: &Word) -> String {
+    let mut expanded = String::new();
+    for part in &word.0 {
+        expand_part(state, part, &mut expanded, regex::escape);
+    }
+    expanded
+}
+
+/// Append the expansion of `part`, with quoted text passed through `quote`
+fn expand_part(
+    state: &ShellState,
+    part: &WordPart,
+    expanded: &mut String,
+    quote: fn(&str) -> String,
+) {
+    match part {
+        WordPart::Literal(s) => *expanded += s,
+        WordPart::Escaped(c) => *expanded += &quote(&c.to_string()),
+        WordPart::SingleQuoted(s) | WordPart::AnsiCQuoted(s) => *expanded += &quote(s),
+        WordPart::DoubleQuoted(parts) => {
+            let mut inner = String::new();
+            for part in parts {
+                expand_part(state, part, &mut inner, str::to_string);
+            }
+            *expanded += &quote(&inner);
+        }
+        WordPart::Parameter(name) => *expanded += &parameter(state, name),
+    }
+}
+
+fn parameter(state: &ShellState, name: &str) -> String {
+    if let Some((name, subscript)) = name.split_once('[') {
+        let array = state.array(name);
+        return match subscript.trim_end_matches(']') {
+            "@" | "*" => array.join(" "),
+            index => index
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| array.get(index).cloned())
+                .unwrap_or_default(),
+        };
+    }
+
+    match name {
+        "0" => state.shell_name().to_string(),
+        "#" => state.positional().len().to_string(),
+        "@" => state.positional().join(" "),
+        // joined with the first character of IFS
+        "*" => {
+            let ifs = state.var("IFS").unwrap_or_else(|| DEFAULT_IFS.to_string());
+            let separator = ifs.chars().next().map(String::from).unwrap_or_default();
+            state.positional().join(&separator)
+        }
+        name if name.bytes().all(|b| b.is_ascii_digit()) => name
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| state.positional().get(n.checked_sub(1)?).cloned())
+            .unwrap_or_default(),
+        "$" => std::process::id().to_string(),
+        "?" => state.status().to_string(),
+        "-" => state.flags(),
+        "!" => state
+            .background_pid()
+            .map(|pid| pid.to_string())
+            .unwrap_or_default(),
+        name => state.var(name).unwrap_or_default(),
+    }
+}
+
+/// Field being built from the parts of a word
+#[derive(Debug, Default)]
+struct Field {
+    /// Text after quote removal
+    text: String,
+    /// Text with quoted characters escaped, for pathname expansion
+    pattern: String,
+    /// Has an unquoted `*`, `?` or `[`
+    glob: bool,
+    /// Kept even if empty, because it was quoted or delimited by a separator
+    keep: bool,
+}
+
+impl Field {
+    fn push_unquoted(&mut self, s: &str) {
+        self.text += s;
+        self.pattern += s;
+        self.glob |= s.contains(['*', '?', '[']);
+    }
+
+    fn push_quoted(&mut self, s: &str) {
+        self.text += s;
+        self.pattern += &glob::Pattern::escape(s);
+        self.keep = true;
+    }
+
+    /// Paths matching the field, or the field itself if none matches unless
+    /// `nullglob` is set
+    fn expand_pathname(self, state: &ShellState) -> Vec<String> {
+        if !self.glob {
+            return vec![self.text];
+        }
+
+        let options = glob::MatchOptions {
+            require_literal_leading_dot: !state.shopt(ShoptOption::Dotglob),
+            ..Default::default()
+        };
+        // `**` is only recursive with globstar, otherwise it is the same as `*`
+        let mut pattern = self.pattern;
+        while !state.shopt(ShoptOption::Globstar) && pattern.contains("**") {
+            pattern = pattern.replace("**", "*");
+        }
+        let mut paths: Vec<String> = match glob::glob_with(&pattern, options) {
+            Ok(paths) => paths
+                .filter_map(|path| path.ok())
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+            Err(_) => vec![],
+        };
+        match paths.is_empty() {
+            true if state.shopt(ShoptOption::Nullglob) => vec![],
+            true => vec![self.text],
+            false => {
+                paths.sort();
+                paths
+            }
+        }
+    }
+}
+
+/// Expand the parts of a word, splitting the results of unquoted expansions
+fn split_word(state: &ShellState, word: &Word, ifs: &str) -> Vec<Field> {
+    let mut fields = vec![];
+    let mut field = Field::default();
+    for (i, part) in word.0.iter().enumerate() {
+        match part {
+            WordPart::Literal(s) if i == 0 => field.push_unquoted(&shellexpand::tilde(s)),
+            WordPart::Literal(s) => field.push_unquoted(s),
+            WordPart::Parameter(name) => {
+                let value = parameter(state, name);
+                let (split, leading, trailing) = split_fields(&value, ifs);
+                if leading {
+                    fields.push(std::mem::take(&mut field));
+                }
+                for (i, s) in split.iter().enumerate() {
+                    if i > 0 {
+                        fields.push(std::mem::take(&mut field));
+                    }
+                    field.push_unquoted(s);
+                    field.keep |= split.len() > 1;
+                }
+                if trailing {
+                    fields.push(std::mem::take(&mut field));
+                }
+            }
+            WordPart::DoubleQuoted(parts) => {
+                // `""` is an empty field, but not a `"$@"` without values
+                if !parts.iter().any(splits_quoted) {
+                    field.push_quoted("");
+                }
+                for part in parts {
+                    match part {
+                        WordPart::Parameter(name) if splits_quoted(part) => {
+                            for (i, value) in quoted_fields(state, name).iter().enumerate() {
+                                if i > 0 {
+                                    fields.push(std::mem::take(&mut field));
+                                }
+                                field.push_quoted(value);
+                            }
+                        }
+                        part => {
+                            let mut quoted = String::new();
+                            expand_part(state, part, &mut quoted, str::to_string);
+                            field.push_quoted(&quoted);
+                        }
+                    }
+                }
+            }
+            part => {
+                let mut quoted = String::new();
+                expand_part(state, part, &mut quoted, str::to_string);
+                field.push_quoted(&quoted);
+            }
+        }
+    }
+    fields.push(field);
+
+    fields
+        .into_iter()
+        .filter(|field| field.keep || !field.text.is_empty())
+        .collect()
+}
+
+/// Whether a part is `$@` or `${name[@]}`, which expand to a field for each
+/// value when double quoted
+fn splits_quoted(part: &WordPart) -> bool {
+    matches!(part, WordPart::Parameter(name) if name == "@" || name.ends_with("[@]"))
+}
+
+fn quoted_fields(state: &ShellState, name: &str) -> Vec<String> {
+    match name.strip_suffix("[@]") {
+        Some(name) => state.array(name),
+        None => state.positional().to_vec(),
+    }
+}
+
+/// Split `value` on the characters of `ifs`.
+///
+/// Runs of IFS whitespace are a single separator and are ignored at both ends,
+/// other IFS characters each end a field. Also returns whether the value starts
+/// or ends with a separator, which ends the surrounding field.
+fn split_fields(value: &str, ifs: &str) -> (Vec<String>, bool, bool) {
+    let is_space = |c: char| ifs.contains(c) && DEFAULT_IFS.contains(c);
+    let start = value.trim_start_matches(is_space);
+    let trimmed = start.trim_end_matches(is_space);
+    let leading = start.len() < value.len();
+    let trailing = trimmed.len() < start.len() || trimmed.ends_with(|c| ifs.contains(c));
+
+    let mut fields = vec![];
+    let mut current = String::new();
+    let mut chars = trimmed.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !ifs.contains(c) {
+            current.push(c);
+            continue;
+        }
+        // whitespace around at most one other separator
+        let mut delimited = !is_space(c);
+        while let Some(&next) = chars.peek() {
+            match is_space(next) || (ifs.contains(next) && !delimited) {
+                true => {
+                    delimited |= !is_space(next);
+                    chars.next();
+                }
+                false => break,
+            }
+        }
+        fields.push(std::mem::take(&mut current));
+    }
+    if !current.is_empty() {
+        fields.push(current);
+    }
+
+    (fields, leading, trailing)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn split(value: &str, ifs: &str) -> Vec<String> {
+        split_fields(value, ifs).0
+    }
+
+    #[test]
+    fn field_splitting() {
+        assert_eq!(split("  a  b\tc\n", DEFAULT_IFS), ["a", "b", "c"]);
+        assert_eq!(split("a b", ""), ["a b"]);
+        assert_eq!(split("a:b::c:", ":"), ["a", "b", "", "c"]);
+        assert_eq!(split(":a", ":"), ["", "a"]);
+        assert_eq!(split(" a : b ", ": "), ["a", "b"]);
+        assert!(split("   ", DEFAULT_IFS).is_empty());
+        assert_eq!(
+            split_fields(" a ", DEFAULT_IFS),
+            (vec!["a".into()], true, true)
+        );
+        assert_eq!(split_fields("a:", ":"), (vec!["a".into()], false, true));
+    }
+}
@@ -1,49 +1,165 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, thread::JoinHandle};
 
-use crate::io::{PErr, PIn, POut};
+use crate::{
+    ast::{CompoundCommand, List},
+    io::{pipe, write_stderr, Io, PErr, PIn, POut},
+    job::{give_terminal, interrupt, take_terminal, Job, JobState, Process, STOPPED_STATUS},
+    state::{ShellOption, ShellState},
+    utils::ResolveError,
+};
 use anyhow::Result;
 use execute::{Execute, MaybeBlockedCommand};
 use strum::{AsRefStr, EnumIter, EnumString};
 
+mod conditional;
+mod eval;
 mod execute;
+mod expand;
+mod read;
+mod time;
+
+pub(crate) use eval::{execute_input, execute_list};
 
 #[derive(Debug)]
 pub(crate) struct PipeCommands {
     pub commands: Vec<StdioCommand>,
+    /// Command line of the pipeline, shown for its job
+    pub text: String,
 }
 
 impl PipeCommands {
-    pub fn execute(mut self) -> Result<()> {
-        // set up pipe
-        for i in 0..self.commands.len() - 1 {
-            let (tx, rx) = std::sync::mpsc::channel();
+    /// Execute the pipeline in the foreground, returning the exit status of
+    /// every command
+    pub fn execute(self, state: &mut ShellState, io: &Io) -> Result<Vec<i32>> {
+        let (job, copies) = self.start(state, io, true)?;
+        let statuses = wait_foreground(state, job, &mut io.try_clone()?.stderr)?;
+        if !statuses.contains(&STOPPED_STATUS) {
+            for copy in copies {
+                copy.join().expect("cannot join output copy");
+            }
+        }
+        Ok(statuses)
+    }
 
-            // first remove stdout in stdio_command
-            self.commands[i]
-                .stdout
-                .retain(|p| !matches!(p, POut::Std(_)));
-            // then add new shared data into stdout
-            self.commands[i].stdout.push(POut::Pipe(tx));
+    /// Start the pipeline as a background job, returning its id
+    pub fn spawn(self, state: &mut ShellState, io: &Io) -> Result<usize> {
+        let (job, _) = self.start(state, io, false)?;
+        if let Some(pid) = job.last_pid() {
+            state.set_background_pid(pid);
+        }
+        Ok(state.jobs_mut().add(job))
+    }
 
-            // add this shared data into the next command's stdin
-            self.commands[i + 1].stdin = PIn::Pipe(rx);
+    /// Start every command, returning the job and the threads copying outputs
+    fn start(
+        mut self,
+        state: &mut ShellState,
+        io: &Io,
+        foreground: bool,
+    ) -> Result<(Job, Vec<JoinHandle<()>>)> {
+        // connect each command to the next with a pipe, a command run by the
+        // shell writes into it from a thread as the reader may only run later
+        for i in 0..self.commands.len() - 1 {
+            let (reader, writer) = pipe()?;
+            let writer = match self.commands[i].is_external() {
+                true => POut::Pipe(writer),
+                false => POut::bridge(writer),
+            };
+            self.commands[i].stdout.push(writer);
+            self.commands[i + 1].stdin = PIn::Pipe(reader);
         }
 
-        let output: Result<Vec<MaybeBlockedCommand>> = self
-            .commands
+        // external commands run concurrently, so start them before the
+        // commands run by the shell that read from or write into their pipes.
+        // With job control they join the process group of the first one.
+        let monitor = state.option(ShellOption::Monitor);
+        let mut pgid = None;
+        let mut outputs: Vec<Option<MaybeBlockedCommand>> = vec![];
+        let mut internal = vec![];
+        for (i, mut command) in self.commands.into_iter().enumerate() {
+            command.inherit(io)?;
+            match &mut command.inner {
+                Command::Path(PathCommand {
+                    process_group,
+                    foreground: takes_terminal,
+                    ..
+                })
+                | Command::Subshell(SubshellCommand {
+                    process_group,
+                    foreground: takes_terminal,
+                    ..
+                }) => {
+                    *process_group = monitor.then_some(pgid.unwrap_or(0));
+                    *takes_terminal = monitor && foreground;
+                    let output = command.execute(state)?;
+                    if let MaybeBlockedCommand::Block { pid, .. } = output {
+                        pgid.get_or_insert(pid);
+                    }
+                    outputs.push(Some(output));
+                }
+                _ => {
+                    outputs.push(None);
+                    internal.push((i, command));
+                }
+            }
+        }
+        for (i, command) in internal {
+            outputs[i] = Some(command.execute(state)?);
+        }
+
+        let mut copies = vec![];
+        let processes = outputs
             .into_iter()
-            .map(|command| command.execute())
+            .flatten()
+            .map(|output| match output {
+                MaybeBlockedCommand::NonBlock(status) => Process::finished(status),
+                MaybeBlockedCommand::Block { pid, copies: c } => {
+                    copies.extend(c);
+                    Process::new(pid)
+                }
+            })
             .collect();
-        let mut outputs = output?;
+        let pgid = pgid.filter(|_| monitor);
+        Ok((Job::new(self.text, pgid, processes), copies))
+    }
+}
 
-        // only wait for the last execution, then kill others
-        outputs.pop().and_then(|out| out.wait().ok());
-        for out in outputs {
-            out.kill()?;
-        }
+/// Wait for a job in the foreground, giving it the terminal with job control.
+///
+/// A job that stops is put in the job table and its status is
+/// [`STOPPED_STATUS`].
+pub(super) fn wait_foreground(
+    state: &mut ShellState,
+    mut job: Job,
+    stderr: &mut [PErr],
+) -> Result<Vec<i32>> {
+    let terminal = job.pgid.filter(|_| state.option(ShellOption::Monitor));
+    if let Some(pgid) = terminal {
+        give_terminal(pgid)?;
+    }
+    let result = job.wait();
+    if terminal.is_some() {
+        take_terminal()?;
+    }
+    result?;
 
-        Ok(())
+    if job.state != JobState::Stopped {
+        let statuses = job.statuses();
+        // the job got Ctrl-C in place of the shell, which stops as well
+        if terminal.is_some() && job.interrupted() {
+            interrupt();
+        }
+        return Ok(statuses);
     }
+    let id = state.jobs_mut().add(job);
+    state.jobs_mut().touch(id);
+    if let Some(job) = state.jobs_mut().get_mut(id) {
+        write_stderr(
+            stderr,
+            format!("\n{}\n", job.describe('+', false)).as_bytes(),
+        )?;
+    }
+    Ok(vec![STOPPED_STATUS])
 }
 
 /// Command that supports reading from stdin and writing to stdout
@@ -65,28 +181,83 @@ impl StdioCommand {
         }
     }
 
-    pub fn execute(mut self) -> Result<MaybeBlockedCommand> {
-        self.inner.execute(self.stdin, self.stdout, self.stderr)
+    /// Use the streams of `io` for those that are neither redirected nor piped
+    fn inherit(&mut self, io: &Io) -> Result<()> {
+        if matches!(self.stdin, PIn::Empty) {
+            self.stdin = io.stdin.try_clone()?;
+        }
+        if self.stdout.is_empty() {
+            self.stdout = io.try_clone()?.stdout;
+        }
+        if self.stderr.is_empty() {
+            self.stderr = io.try_clone()?.stderr;
+        }
+        Ok(())
+    }
+
+    /// Whether the command runs in a child process rather than in the shell
+    fn is_external(&self) -> bool {
+        matches!(self.inner, Command::Path(_) | Command::Subshell(_))
+    }
+
+    pub fn execute(mut self, state: &mut ShellState) -> Result<MaybeBlockedCommand> {
+        self.inner
+            .execute(state, self.stdin, self.stdout, self.stderr)
     }
 }
 
 #[derive(Debug)]
 pub(crate) enum Command {
-    Builtin(BuiltinCommand),
+    /// A builtin, with the variables assigned for its execution only
+    Builtin(BuiltinCommand, Vec<(String, String)>),
     Invalid(InvalidCommand),
     Path(PathCommand),
+    Compound(CompoundCommand),
+    Subshell(SubshellCommand),
+    /// No command name, only variable assignments and redirects
+    Assign(Vec<(String, String)>),
 }
 
 #[derive(Debug, Default, PartialEq)]
 pub(crate) struct CommandArgs(pub Vec<String>);
 
+/// Command name that does not resolve to an executable file
 #[derive(Debug, Default, PartialEq)]
-pub(crate) struct InvalidCommand(pub String);
+pub(crate) struct InvalidCommand(pub String, pub ResolveError);
 
 #[derive(Debug, Default, PartialEq)]
 pub(crate) struct PathCommand {
+    /// Executable file the name resolved to
     pub path: PathBuf,
+    /// Name the command was invoked with, its `argv[0]`
+    pub name: String,
     pub args: CommandArgs,
+    /// Variables only exported to this command
+    pub env: Vec<(String, String)>,
+    /// Process group to join, 0 for a new one, or `None` to stay in the shell's
+    pub process_group: Option<libc::pid_t>,
+    /// Whether the process group takes the terminal, for a foreground job
+    pub foreground: bool,
+}
+
+/// Commands run by a copy of the shell in a child process, such as `( list )`
+#[derive(Debug, PartialEq)]
+pub(crate) struct SubshellCommand {
+    pub list: List,
+    /// Process group to join, 0 for a new one, or `None` to stay in the shell's
+    pub process_group: Option<libc::pid_t>,
+    /// Whether the process group takes the terminal, for a foreground job
+    pub foreground: bool,
+}
+
+impl SubshellCommand {
+    pub fn new(list: List) -> SubshellCommand {
+        SubshellCommand {
+            list,
+            process_group: None,
+            foreground: false,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, EnumString, EnumIter, AsRefStr)]
@@ -103,6 +274,40 @@ pub(crate) enum BuiltinCommand {
     Cd(CommandArgs),
     #[strum(serialize = "history")]
     History(CommandArgs),
+    #[strum(serialize = "alias")]
+    Alias(CommandArgs),
+    #[strum(serialize = "unalias")]
+    Unalias(CommandArgs),
+    #[strum(serialize = "test")]
+    Test(CommandArgs),
+    #[strum(serialize = "[")]
+    Bracket(CommandArgs),
+    #[strum(serialize = "set")]
+    Set(CommandArgs),
+    #[strum(serialize = "read")]
+    Read(CommandArgs),
+    #[strum(serialize = "source")]
+    Source(CommandArgs),
+    #[strum(serialize = ".")]
+    Dot(CommandArgs),
+    #[strum(serialize = "jobs")]
+    Jobs(CommandArgs),
+    #[strum(serialize = "fg")]
+    Fg(CommandArgs),
+    #[strum(serialize = "bg")]
+    Bg(CommandArgs),
+    #[strum(serialize = "hash")]
+    Hash(CommandArgs),
+    #[strum(serialize = "shift")]
+    Shift(CommandArgs),
+    #[strum(serialize = "return")]
+    Return(CommandArgs),
+    #[strum(serialize = "export")]
+    Export(CommandArgs),
+    #[strum(serialize = "unset")]
+    Unset(CommandArgs),
+    #[strum(serialize = "shopt")]
+    Shopt(CommandArgs),
 }
 
 impl BuiltinCommand {
@@ -114,6 +319,23 @@ impl BuiltinCommand {
             BuiltinCommand::Pwd => BuiltinCommand::Pwd,
             BuiltinCommand::Cd(_) => BuiltinCommand::Cd(args),
             BuiltinCommand::History(_) => BuiltinCommand::History(args),
+            BuiltinCommand::Alias(_) => BuiltinCommand::Alias(args),
+            BuiltinCommand::Unalias(_) => BuiltinCommand::Unalias(args),
+            BuiltinCommand::Test(_) => BuiltinCommand::Test(args),
+            BuiltinCommand::Bracket(_) => BuiltinCommand::Bracket(args),
+            BuiltinCommand::Set(_) => BuiltinCommand::Set(args),
+            BuiltinCommand::Read(_) => BuiltinCommand::Read(args),
+            BuiltinCommand::Source(_) => BuiltinCommand::Source(args),
+            BuiltinCommand::Dot(_) => BuiltinCommand::Dot(args),
+            BuiltinCommand::Jobs(_) => BuiltinCommand::Jobs(args),
+            BuiltinCommand::Fg(_) => BuiltinCommand::Fg(args),
+            BuiltinCommand::Bg(_) => BuiltinCommand::Bg(args),
+            BuiltinCommand::Hash(_) => BuiltinCommand::Hash(args),
+            BuiltinCommand::Shift(_) => BuiltinCommand::Shift(args),
+            BuiltinCommand::Return(_) => BuiltinCommand::Return(args),
+            BuiltinCommand::Export(_) => BuiltinCommand::Export(args),
+            BuiltinCommand::Unset(_) => BuiltinCommand::Unset(args),
+            BuiltinCommand::Shopt(_) => BuiltinCommand::Shopt(args),
         }
     }
 }
@@ -0,0 +1,328 @@
+use std::{
+    os::fd::RawFd,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::io::PIn;
+
+/// Character of a line read by `read`, with whether a backslash escaped it
+pub(super) type ReadChar = (char, bool);
+
+/// Options of the `read` builtin
+#[derive(Debug, PartialEq)]
+pub(super) struct ReadOptions {
+    /// `-r`, backslashes are ordinary characters
+    pub raw: bool,
+    /// `-p`, printed before reading from a terminal
+    pub prompt: Option<String>,
+    /// `-a`, array getting every field
+    pub array: Option<String>,
+    /// `-d`, byte ending the input instead of a newline
+    pub delimiter: u8,
+    /// `-n`, number of characters to read at most
+    pub count: Option<usize>,
+    /// `-t`, time to wait for the input
+    pub timeout: Option<Duration>,
+    /// `-s`, do not echo the input of a terminal
+    pub silent: bool,
+    pub names: Vec<String>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            raw: false,
+            prompt: None,
+            array: None,
+            delimiter: b'\n',
+            count: None,
+            timeout: None,
+            silent: false,
+            names: vec![],
+        }
+    }
+}
+
+impl ReadOptions {
+    /// Parse options the way `getopts` does, flags can be grouped and option
+    /// arguments attached, as in `-rsp'> '`
+    pub fn parse(args: &[String]) -> Result<ReadOptions, String> {
+        let mut options = ReadOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let flags = match arg.strip_prefix('-') {
+                Some("-") => break,
+                Some(flags) if !flags.is_empty() => flags,
+                _ => {
+                    options.names.push(arg.clone());
+                    break;
+                }
+            };
+
+            let mut chars = flags.chars();
+            while let Some(flag) = chars.next() {
+                match flag {
+                    'r' => options.raw = true,
+                    's' => options.silent = true,
+                    'p' | 'a' | 'd' | 'n' | 't' => {
+                        let value = match chars.as_str() {
+                            "" => args
+                                .next()
+                                .cloned()
+                                .ok_or_else(|| format!("-{flag}: option requires an argument"))?,
+                            attached => attached.to_string(),
+                        };
+                        options.set(flag, value)?;
+                        break;
+                    }
+                    flag => return Err(format!("-{flag}: invalid option")),
+                }
+            }
+        }
+        options.names.extend(args.cloned());
+        Ok(options)
+    }
+
+    fn set(&mut self, flag: char, value: String) -> Result<(), String> {
+        match flag {
+            'p' => self.prompt = Some(value),
+            'a' => self.array = Some(value),
+            // an empty delimiter is NUL
+            'd' => self.delimiter = value.bytes().next().unwrap_or(0),
+            'n' => {
+                let count = value
+                    .parse()
+                    .map_err(|_| format!("{value}: invalid number"))?;
+                self.count = Some(count);
+            }
+            _ => {
+                let timeout = value
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or_else(|| format!("{value}: invalid timeout specification"))?;
+                self.timeout = Some(timeout);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How reading the input stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum ReadEnd {
+    /// The delimiter was read, or as many characters as asked for
+    Complete,
+    Eof,
+    Timeout,
+}
+
+/// Read the input of `read`, up to the delimiter.
+///
+/// Unless raw, a backslash before a newline joins lines and a backslash
+/// before the delimiter makes it part of the input. The backslashes are kept
+/// for [`unescape`].
+pub(super) fn read_input(stdin: &mut PIn, options: &ReadOptions) -> Result<(String, ReadEnd)> {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut input = vec![];
+    let mut chars = 0;
+    let mut escaped = false;
+    let end = loop {
+        if options.count.is_some_and(|count| chars >= count) {
+            break ReadEnd::Complete;
+        }
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !stdin.poll(remaining)? {
+                break ReadEnd::Timeout;
+            }
+        }
+
+        let Some(byte) = stdin.read_byte()? else {
+            break ReadEnd::Eof;
+        };
+        match byte {
+            // a line continuation
+            b'\n' if escaped => {
+                input.pop();
+                chars -= 1;
+            }
+            byte if byte == options.delimiter && !escaped => break ReadEnd::Complete,
+            byte => {
+                input.push(byte);
+                // the rest of a multibyte character
+                let width = match byte {
+                    0xf0.. => 4,
+                    0xe0.. => 3,
+                    0xc0.. => 2,
+                    _ => 1,
+                };
+                for _ in 1..width {
+                    input.extend(stdin.read_byte()?);
+                }
+                chars += 1;
+            }
+        }
+        escaped = !options.raw && byte == b'\\' && !escaped;
+    };
+    Ok((String::from_utf8_lossy(&input).into_owned(), end))
+}
+
+/// Changes the modes of a terminal until dropped, for `read -s`, `-n` and `-d`
+pub(super) struct TerminalMode {
+    fd: RawFd,
+    termios: libc::termios,
+}
+
+impl TerminalMode {
+    /// Turn off the echo of `fd` if `silent`, and its line editing if
+    /// `unbuffered` so that every byte is read as soon as it is typed.
+    /// `None` if it is not a terminal.
+    pub fn new(fd: RawFd, silent: bool, unbuffered: bool) -> Option<TerminalMode> {
+        // SAFETY: tcgetattr only writes into the struct it is given
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+            return None;
+        }
+        let mut mode = termios;
+        if silent {
+            mode.c_lflag &= !libc::ECHO;
+        }
+        if unbuffered {
+            mode.c_lflag &= !libc::ICANON;
+            mode.c_cc[libc::VMIN] = 1;
+            mode.c_cc[libc::VTIME] = 0;
+        }
+        // SAFETY: the settings were read from the same terminal
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &mode) };
+        Some(TerminalMode { fd, termios })
+    }
+}
+
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in `new`
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.termios) };
+    }
+}
+
+/// Remove the backslashes of a line, the escaped characters lose their
+/// special meaning
+pub(super) fn unescape(line: &str) -> Vec<ReadChar> {
+    let mut chars = vec![];
+    let mut iter = line.chars();
+    while let Some(c) = iter.next() {
+        match c {
+            // a lone backslash at the end of the input is dropped
+            '\\' => chars.extend(iter.next().map(|c| (c, true))),
+            c => chars.push((c, false)),
+        }
+    }
+    chars
+}
+
+/// Split a line on the characters of `ifs` into `count` values, or as many
+/// as there are fields.
+///
+/// The last of `count` values gets the rest of the line, without the IFS
+/// whitespace around it. Values are empty when there are fewer fields.
+pub(super) fn split_line(line: &[ReadChar], ifs: &str, count: Option<usize>) -> Vec<String> {
+    let is_ifs = |&(c, escaped): &ReadChar| !escaped && ifs.contains(c);
+    let is_space = |c: &ReadChar| is_ifs(c) && " \t\n".contains(c.0);
+    let collect = |chars: &[ReadChar]| chars.iter().map(|&(c, _)| c).collect::<String>();
+
+    let mut values = vec![];
+    let mut rest = trim_start(line, is_space);
+    while count.is_none_or(|count| values.len() + 1 < count) && !rest.is_empty() {
+        let end = rest.iter().position(is_ifs).unwrap_or(rest.len());
+        values.push(collect(&rest[..end]));
+
+        // whitespace around at most one other separator
+        rest = trim_start(&rest[end..], is_space);
+        if rest.first().is_some_and(|c| is_ifs(c) && !is_space(c)) {
+            rest = trim_start(&rest[1..], is_space);
+        }
+    }
+    if let Some(count) = count {
+        if values.len() < count {
+            let end = rest.iter().rposition(|c| !is_space(c)).map_or(0, |i| i + 1);
+            values.push(collect(&rest[..end]));
+        }
+        values.resize(count, String::new());
+    }
+    values
+}
+
+fn trim_start(chars: &[ReadChar], is_space: impl Fn(&ReadChar) -> bool) -> &[ReadChar] {
+    let start = chars.iter().position(|c| !is_space(c));
+    start.map_or(&[], |start| &chars[start..])
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn split(line: &str, ifs: &str, count: usize) -> Vec<String> {
+        split_line(&unescape(line), ifs, Some(count))
+    }
+
+    fn options(args: &str) -> Result<ReadOptions, String> {
+        let args: Vec<String> = args.split(' ').map(String::from).collect();
+        ReadOptions::parse(&args)
+    }
+
+    #[test]
+    fn split_into_names() {
+        assert_eq!(split("  a  b c  ", " \t\n", 2), ["a", "b c"]);
+        assert_eq!(split("a", " \t\n", 3), ["a", "", ""]);
+        assert_eq!(split("a:b::c", ":", 3), ["a", "b", ":c"]);
+        assert_eq!(split("a : b", " :", 2), ["a", "b"]);
+        assert_eq!(split(r"a\ b c", " ", 2), ["a b", "c"]);
+        assert_eq!(
+            split_line(&unescape(" a  b:c: "), " :", None),
+            ["a", "b", "c"]
+        );
+        assert_eq!(
+            unescape(r"a\\b\"),
+            [('a', false), ('\\', true), ('b', false)]
+        );
+    }
+
+    #[test]
+    fn parse_options() {
+        assert_eq!(
+            options("-rsp> -a arr -d: -n 3 -t 0.5 x y"),
+            Ok(ReadOptions {
+                raw: true,
+                prompt: Some(">".into()),
+                array: Some("arr".into()),
+                delimiter: b':',
+                count: Some(3),
+                timeout: Some(Duration::from_millis(500)),
+                silent: true,
+                names: vec!["x".into(), "y".into()],
+            })
+        );
+        assert_eq!(
+            options("-r -- -n"),
+            Ok(ReadOptions {
+                raw: true,
+                names: vec!["-n".into()],
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            options("-d").map(|o| o.delimiter),
+            Err("-d: option requires an argument".into())
+        );
+        assert_eq!(options("-x"), Err("-x: invalid option".into()));
+        assert_eq!(options("-n a"), Err("a: invalid number".into()));
+        assert_eq!(
+            options("-t -1"),
+            Err("-1: invalid timeout specification".into())
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+/// Format used when `TIMEFORMAT` is unset
+pub(super) const DEFAULT_TIMEFORMAT: &str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS";
+
+/// Format of `time -p`
+pub(super) const POSIX_TIMEFORMAT: &str = "real %2R\nuser %2U\nsys %2S";
+
+/// CPU time used by the shell and the children it has waited for.
+///
+/// Children of compound commands are waited deep inside the executor, so the
+/// usage is sampled before and after the pipeline instead of being collected
+/// from each child.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(super) struct CpuTime {
+    pub user: Duration,
+    pub sys: Duration,
+}
+
+impl CpuTime {
+    pub fn now() -> CpuTime {
+        let shell = rusage(libc::RUSAGE_SELF);
+        let children = rusage(libc::RUSAGE_CHILDREN);
+        CpuTime {
+            user: shell.user + children.user,
+            sys: shell.sys + children.sys,
+        }
+    }
+
+    /// Time used since `start` was sampled
+    pub fn since(start: CpuTime) -> CpuTime {
+        let now = CpuTime::now();
+        CpuTime {
+            user: now.user.saturating_sub(start.user),
+            sys: now.sys.saturating_sub(start.sys),
+        }
+    }
+}
+
+fn rusage(who: libc::c_int) -> CpuTime {
+    let timeval = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    };
+
+    // SAFETY: getrusage only writes into the struct it is given
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    match unsafe { libc::getrusage(who, &mut usage) } {
+        0 => CpuTime {
+            user: timeval(usage.ru_utime),
+            sys: timeval(usage.ru_stime),
+        },
+        _ => CpuTime::default(),
+    }
+}
+
+/// Expand the `%` escapes of a `TIMEFORMAT` string.
+///
+/// `%[p][l]R`, `%[p][l]U` and `%[p][l]S` are the real, user and system times
+/// with `p` decimals (3 at most) and `l` for the `MMmSS.FFs` form, `%P` is the
+/// CPU percentage and `%%` a literal `%`.
+pub(super) fn format_times(format: &str, real: Duration, cpu: CpuTime) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut spec = String::from("%");
+        let precision = match chars.peek().and_then(|c| c.to_digit(10)) {
+            Some(digit) => {
+                spec.push(chars.next().unwrap_or_default());
+                digit.min(3) as usize
+            }
+            None => 3,
+        };
+        let long = chars.next_if_eq(&'l').is_some();
+        if long {
+            spec.push('l');
+        }
+
+        let time = match chars.next() {
+            Some('R') => real,
+            Some('U') => cpu.user,
+            Some('S') => cpu.sys,
+            Some('P') if spec == "%" => {
+                let cpu = (cpu.user + cpu.sys).as_secs_f64();
+                let percent = match real.as_secs_f64() {
+                    0.0 => 0.0,
+                    real => cpu / real * 100.0,
+                };
+                out += &format!("{percent:.2}");
+                continue;
+            }
+            Some('%') if spec == "%" => {
+                out.push('%');
+                continue;
+            }
+            Some(c) => {
+                out += &spec;
+                out.push(c);
+                continue;
+            }
+            None => {
+                out += &spec;
+                continue;
+            }
+        };
+
+        let seconds = time.as_secs_f64();
+        out += &match long {
+            true => {
+                let minutes = (seconds / 60.0).floor();
+                format!("{minutes}m{:.precision$}s", seconds - minutes * 60.0)
+            }
+            false => format!("{seconds:.precision$}"),
+        };
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn timeformat() {
+        let cpu = CpuTime {
+            user: Duration::from_millis(1500),
+            sys: Duration::from_millis(250),
+        };
+        let real = Duration::from_millis(61_250);
+        assert_eq!(
+            format_times(DEFAULT_TIMEFORMAT, real, cpu),
+            "\nreal\t1m1.250s\nuser\t0m1.500s\nsys\t0m0.250s"
+        );
+        assert_eq!(
+            format_times(POSIX_TIMEFORMAT, real, cpu),
+            "real 61.25\nuser 1.50\nsys 0.25"
+        );
+        assert_eq!(
+            format_times("%0R %9U %P%% %x", real, cpu),
+            "61 1.500 2.86% %x"
+        );
+    }
+}
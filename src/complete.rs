@@ -1,4 +1,11 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashSet},
+    env, fs,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    time::SystemTime,
+};
 
 use rustyline::{
     completion::{Candidate, Completer},
@@ -41,7 +48,54 @@ impl Candidate for CompleteCandidate {
     }
 }
 
-pub(crate) struct ShellCompleter {}
+#[derive(Default)]
+pub(crate) struct ShellCompleter {
+    /// Value of PATH, set by the shell before each prompt
+    path: Option<String>,
+    /// Number of times the shell cleared its hash table, see `set_path`
+    hash_resets: usize,
+    /// Names of the executables in PATH, listed again when PATH changes or
+    /// one of its directories is modified
+    commands: RefCell<Option<PathCommands>>,
+}
+
+/// Executables found in the directories of PATH
+struct PathCommands {
+    /// Each directory with its modification time when it was listed
+    dirs: Vec<(PathBuf, Option<SystemTime>)>,
+    names: BTreeSet<String>,
+}
+
+impl ShellCompleter {
+    /// Update PATH before a prompt, the executables are listed again if it
+    /// changed or if the hash table was cleared since the last prompt
+    pub fn set_path(&mut self, path: Option<String>, hash_resets: usize) {
+        if path != self.path || hash_resets != self.hash_resets {
+            self.path = path;
+            self.hash_resets = hash_resets;
+            self.commands = RefCell::default();
+        }
+    }
+
+    fn complete_path(&self, word: &str) -> Vec<CompleteCandidate> {
+        let dirs = path_dirs(self.path.as_deref());
+        let mut commands = self.commands.borrow_mut();
+        if commands
+            .as_ref()
+            .is_none_or(|commands| commands.dirs != dirs)
+        {
+            let names = list_commands(&dirs);
+            *commands = Some(PathCommands { dirs, names });
+        }
+        let names = &commands.as_ref().unwrap().names;
+        names
+            .range(word.to_string()..)
+            .take_while(|command| command.starts_with(word))
+            .map(|command| CompleteCandidate::new(command))
+            .collect()
+    }
+}
+
 impl Completer for ShellCompleter {
     type Candidate = CompleteCandidate;
 
@@ -69,7 +123,7 @@ impl Completer for ShellCompleter {
         let pairs: HashSet<CompleteCandidate> = HashSet::from_iter(
             complete_builtin(word)
                 .into_iter()
-                .chain(complete_path(word)),
+                .chain(self.complete_path(word)),
         );
         let mut pairs: Vec<CompleteCandidate> = pairs.into_iter().collect();
         pairs.sort();
@@ -91,28 +145,32 @@ fn complete_builtin(word: &str) -> Vec<CompleteCandidate> {
         .collect()
 }
 
-fn complete_path(word: &str) -> Vec<CompleteCandidate> {
-    match std::env::var("PATH") {
-        Ok(path) => path
-            .split(":")
-            // construct absolute path
-            .map(|p| format!("{}/{}*", p, word))
-            // only find path that exists
-            .filter_map(|pattern| glob::glob(&pattern).ok())
-            // convert entries to path
-            .flat_map(|entries| {
-                let entries: Vec<PathBuf> =
-                    entries.into_iter().filter_map(|entry| entry.ok()).collect();
-                entries
-            })
-            // only use file name as executable file
-            .filter_map(|p| {
-                p.file_name()
-                    .and_then(|f| f.to_str())
-                    .map(|s| s.to_string())
-                    .map(|s| CompleteCandidate::new(&s))
+/// Directories of `path` with their modification times, an empty or relative
+/// directory is taken from the current directory
+fn path_dirs(path: Option<&str>) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let current_dir = env::current_dir().unwrap_or_default();
+    path.into_iter()
+        .flat_map(|path| path.split(':'))
+        .map(|dir| {
+            let dir = current_dir.join(dir);
+            let modified = fs::metadata(&dir).and_then(|metadata| metadata.modified());
+            (dir, modified.ok())
+        })
+        .collect()
+}
+
+/// Names of the executable files in `dirs`
+fn list_commands(dirs: &[(PathBuf, Option<SystemTime>)]) -> BTreeSet<String> {
+    dirs.iter()
+        .filter_map(|(dir, _)| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        // follow symbolic links to their executable
+        .filter(|entry| {
+            fs::metadata(entry.path()).is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
             })
-            .collect(),
-        Err(_) => vec![],
-    }
+        })
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
 }
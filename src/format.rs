@@ -0,0 +1,562 @@
+//! Canonical formatting of a syntax tree, in the spirit of `shfmt`.
+//!
+//! One command per line, bodies of compound commands indented, a single space
+//! around `|`, `&&` and `||`, redirects written as `>file`, and words re-quoted
+//! with as little quoting as keeps their meaning.
+
+use crate::{
+    ast::{
+        AndOr, Command, CompoundCommand, ConditionalExpr, Connector, List, Pipeline, Redirect,
+        SimpleCommand, TimeFormat, Word, WordPart,
+    },
+    parse::is_name,
+};
+
+const INDENT: &str = "  ";
+
+/// Words only recognised when unquoted
+const RESERVED_WORDS: [&str; 19] = [
+    "if", "then", "elif", "else", "fi", "do", "done", "case", "esac", "while", "until", "for",
+    "in", "{", "}", "!", "time", "[[", "]]",
+];
+
+/// Characters that must be quoted to be taken literally
+const SPECIAL_CHARS: &str = " \t\n|&;<>()$`\\'\"*?[";
+
+/// Format a parsed command line or script
+pub fn format(list: &List) -> String {
+    let mut formatter = Formatter::default();
+    formatter.list(list);
+    formatter.out
+}
+
+/// Format a pipeline on a single line, as shown by `jobs`
+pub(crate) fn format_pipeline(pipeline: &Pipeline) -> String {
+    let mut formatter = Formatter::default();
+    formatter.pipeline(pipeline);
+    formatter.out
+}
+
+/// Format an and-or list on a single line, as shown by `jobs`
+pub(crate) fn format_and_or(and_or: &AndOr) -> String {
+    let mut formatter = Formatter::default();
+    formatter.and_or(and_or);
+    formatter.out
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    depth: usize,
+}
+
+impl Formatter {
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out += INDENT;
+        }
+    }
+
+    fn comment_lines(&mut self, comments: &[String]) {
+        for comment in comments {
+            self.indent();
+            self.out += &format!("#{comment}\n");
+        }
+    }
+
+    fn list(&mut self, list: &List) {
+        for item in &list.items {
+            self.comment_lines(&item.comments);
+            self.indent();
+            self.and_or(&item.and_or);
+            if item.background {
+                self.out += " &";
+            }
+            if let Some(comment) = &item.comment {
+                self.out += &format!(" #{comment}");
+            }
+            self.out += "\n";
+        }
+        self.comment_lines(&list.comments);
+    }
+
+    /// Indented list, one item per line
+    fn block(&mut self, list: &List) {
+        self.depth += 1;
+        self.list(list);
+        self.depth -= 1;
+    }
+
+    /// `keyword list; closing`, on a single line unless the list has comments
+    fn clause(&mut self, keyword: &str, list: &List, closing: &str) {
+        self.out += keyword;
+        if has_comments(list) {
+            self.out += "\n";
+            self.block(list);
+            self.indent();
+        } else {
+            for item in &list.items {
+                self.out += " ";
+                self.and_or(&item.and_or);
+                self.out += if item.background { " &" } else { ";" };
+            }
+            self.out += " ";
+        }
+        self.out += closing;
+    }
+
+    fn and_or(&mut self, and_or: &AndOr) {
+        self.pipeline(&and_or.first);
+        for (connector, pipeline) in &and_or.rest {
+            self.out += match connector {
+                Connector::And => " && ",
+                Connector::Or => " || ",
+            };
+            self.pipeline(pipeline);
+        }
+    }
+
+    fn pipeline(&mut self, pipeline: &Pipeline) {
+        match pipeline.time {
+            Some(TimeFormat::Default) => self.out += "time ",
+            Some(TimeFormat::Posix) => self.out += "time -p ",
+            None => {}
+        }
+        if pipeline.negated {
+            self.out += "! ";
+        }
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            if i > 0 {
+                self.out += " | ";
+            }
+            self.command(command);
+        }
+    }
+
+    fn command(&mut self, command: &Command) {
+        match command {
+            Command::Simple(simple) => self.simple_command(simple),
+            Command::Compound { body, redirects } => {
+                self.compound_command(body);
+                for redirect in redirects {
+                    self.out += " ";
+                    self.out += &format_redirect(redirect);
+                }
+            }
+        }
+    }
+
+    fn simple_command(&mut self, simple: &SimpleCommand) {
+        let assignments = simple
+            .assignments
+            .iter()
+            .map(|assignment| format!("{}={}", assignment.name, format_word(&assignment.value)));
+        let mut words = simple.words.iter().map(format_word);
+        // a quoted `name=value` command name must not become an assignment
+        let name = words.next().map(|name| match name.split_once('=') {
+            Some((prefix, _)) if is_name(prefix) => format!("\\{name}"),
+            _ => name,
+        });
+        let words: Vec<String> = assignments
+            .chain(name)
+            .chain(words)
+            .chain(simple.redirects.iter().map(format_redirect))
+            .collect();
+        self.out += &words.join(" ");
+    }
+
+    fn compound_command(&mut self, compound: &CompoundCommand) {
+        match compound {
+            CompoundCommand::BraceGroup(list) => {
+                self.out += "{\n";
+                self.block(list);
+                self.indent();
+                self.out += "}";
+            }
+            CompoundCommand::Subshell(list) => {
+                self.out += "(\n";
+                self.block(list);
+                self.indent();
+                self.out += ")";
+            }
+            CompoundCommand::If {
+                branches,
+                otherwise,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    if i > 0 {
+                        self.indent();
+                    }
+                    let keyword = if i == 0 { "if" } else { "elif" };
+                    self.clause(keyword, condition, "then\n");
+                    self.block(body);
+                }
+                if let Some(body) = otherwise {
+                    self.indent();
+                    self.out += "else\n";
+                    self.block(body);
+                }
+                self.indent();
+                self.out += "fi";
+            }
+            CompoundCommand::While { condition, body } => {
+                self.clause("while", condition, "do\n");
+                self.done(body);
+            }
+            CompoundCommand::Until { condition, body } => {
+                self.clause("until", condition, "do\n");
+                self.done(body);
+            }
+            CompoundCommand::For { name, words, body } => {
+                self.out += &format!("for {name}");
+                if let Some(words) = words {
+                    self.out += " in";
+                    for word in words {
+                        self.out += " ";
+                        self.out += &format_word(word);
+                    }
+                }
+                self.out += "; do\n";
+                self.done(body);
+            }
+            CompoundCommand::Case { word, items } => {
+                self.out += &format!("case {} in\n", format_word(word));
+                self.depth += 1;
+                for item in items {
+                    self.indent();
+                    let patterns: Vec<String> = item.patterns.iter().map(format_word).collect();
+                    self.out += &format!("{})\n", patterns.join(" | "));
+                    self.depth += 1;
+                    self.list(&item.body);
+                    self.indent();
+                    self.out += ";;\n";
+                    self.depth -= 1;
+                }
+                self.depth -= 1;
+                self.indent();
+                self.out += "esac";
+            }
+            CompoundCommand::Conditional(expr) => {
+                self.out += &format!("[[ {} ]]", format_conditional(expr, 0));
+            }
+        }
+    }
+
+    /// Loop body followed by `done`
+    fn done(&mut self, body: &List) {
+        self.block(body);
+        self.indent();
+        self.out += "done";
+    }
+}
+
+fn format_redirect(redirect: &Redirect) -> String {
+    let (n, default, op) = match redirect {
+        Redirect::Input { n, .. } => (*n, 0, "<"),
+        Redirect::Output { n, .. } => (*n, 1, ">"),
+        Redirect::AppendOutput { n, .. } => (*n, 1, ">>"),
+    };
+    match n == default {
+        true => format!("{op}{}", format_word(redirect.word())),
+        false => format!("{n}{op}{}", format_word(redirect.word())),
+    }
+}
+
+/// Expression of `[[ ]]`, in parentheses if it binds less tightly than `precedence`
+fn format_conditional(expr: &ConditionalExpr, precedence: u8) -> String {
+    let (text, own) = match expr {
+        ConditionalExpr::Or(lhs, rhs) => {
+            let (lhs, rhs) = (format_conditional(lhs, 0), format_conditional(rhs, 1));
+            (format!("{lhs} || {rhs}"), 0)
+        }
+        ConditionalExpr::And(lhs, rhs) => {
+            let (lhs, rhs) = (format_conditional(lhs, 1), format_conditional(rhs, 2));
+            (format!("{lhs} && {rhs}"), 1)
+        }
+        ConditionalExpr::Not(expr) => (format!("! {}", format_conditional(expr, 2)), 2),
+        ConditionalExpr::Unary { op, operand } => (format!("{op} {}", format_word(operand)), 3),
+        ConditionalExpr::Binary { lhs, op, rhs } => {
+            (format!("{} {op} {}", format_word(lhs), format_word(rhs)), 3)
+        }
+        ConditionalExpr::Word(word) => (format_word(word), 3),
+    };
+    match own < precedence {
+        true => format!("( {text} )"),
+        false => text,
+    }
+}
+
+fn has_comments(list: &List) -> bool {
+    !list.comments.is_empty()
+        || list
+            .items
+            .iter()
+            .any(|item| !item.comments.is_empty() || item.comment.is_some())
+}
+
+/// Re-quote a word with minimal quoting
+fn format_word(word: &Word) -> String {
+    if let Some(literal) = word.as_literal() {
+        return literal.to_string();
+    }
+
+    let parts: Vec<String> = word
+        .0
+        .iter()
+        .enumerate()
+        .map(|(i, part)| match part {
+            WordPart::Literal(s) => s.clone(),
+            WordPart::Escaped(c) => quote(&c.to_string(), i == 0),
+            WordPart::SingleQuoted(s) => quote(s, i == 0),
+            WordPart::AnsiCQuoted(s) if s.chars().any(|c| c.is_control()) => ansi_c_quote(s),
+            WordPart::AnsiCQuoted(s) => quote(s, i == 0),
+            WordPart::DoubleQuoted(parts) => double_quote(parts, i == 0),
+            WordPart::Parameter(_) => String::new(),
+        })
+        .collect();
+
+    let mut out = String::new();
+    for (i, part) in word.0.iter().enumerate() {
+        match part {
+            WordPart::Parameter(name) => {
+                let next = parts.get(i + 1).and_then(|s| s.chars().next());
+                out += &parameter(name, next);
+            }
+            // a literal `$` would start an expansion with the part after it
+            _ if i > 0
+                && matches!(&word.0[i - 1], WordPart::Literal(s) if s.ends_with('$'))
+                && parts[i].starts_with(continues_dollar) =>
+            {
+                out += &quote_after_dollar(part)
+            }
+            _ => out += &parts[i],
+        }
+    }
+
+    if RESERVED_WORDS.contains(&out.as_str()) {
+        return format!("'{out}'");
+    }
+    out
+}
+
+/// Whether `c` after a `$` makes it a parameter expansion or a `$'...'` quote
+fn continues_dollar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_{'?#@*$!-".contains(c)
+}
+
+/// Quoted part after a literal `$`, which keeps its quotes or backslash.
+/// Single quotes would make it a `$'...'` quote, double quotes are used instead.
+fn quote_after_dollar(part: &WordPart) -> String {
+    match part {
+        WordPart::Escaped(c) => format!("\\{c}"),
+        WordPart::SingleQuoted(s) | WordPart::AnsiCQuoted(s) => {
+            format!("\"{}\"", escape_double_quoted(s))
+        }
+        WordPart::DoubleQuoted(parts) => match double_quote(parts, false) {
+            quoted if quoted.starts_with('"') => quoted,
+            _ => format!("\"{}\"", escape_double_quoted(&literal_text(parts))),
+        },
+        WordPart::Literal(s) => s.clone(),
+        WordPart::Parameter(name) => parameter(name, None),
+    }
+}
+
+/// Quote `s` only if it contains characters that would otherwise be interpreted
+fn quote(s: &str, word_start: bool) -> String {
+    let special = s.chars().any(|c| SPECIAL_CHARS.contains(c))
+        || (word_start && (s.starts_with('#') || s.starts_with('~')));
+    if s.is_empty() {
+        "''".to_string()
+    } else if !special {
+        s.to_string()
+    } else if !s.contains('\'') {
+        format!("'{s}'")
+    } else {
+        format!("\"{}\"", escape_double_quoted(s))
+    }
+}
+
+/// Text of the literal parts of a double-quoted part
+fn literal_text(parts: &[WordPart]) -> String {
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            WordPart::Literal(s) => Some(s.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn double_quote(parts: &[WordPart], word_start: bool) -> String {
+    let literal: Option<String> = parts
+        .iter()
+        .map(|part| match part {
+            WordPart::Literal(s) => Some(s.as_str()),
+            _ => None,
+        })
+        .collect();
+    if let Some(literal) = literal {
+        return quote(&literal, word_start);
+    }
+
+    let mut out = String::from("\"");
+    for (i, part) in parts.iter().enumerate() {
+        match part {
+            WordPart::Parameter(name) => {
+                let next = match parts.get(i + 1) {
+                    Some(WordPart::Literal(s)) => s.chars().next(),
+                    _ => None,
+                };
+                out += &parameter(name, next);
+            }
+            WordPart::Literal(s) => out += &escape_double_quoted(s),
+            _ => {}
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `$'...'` quoting, for text containing control characters
+fn ansi_c_quote(s: &str) -> String {
+    let mut out = String::from("$'");
+    for c in s.chars() {
+        match c {
+            '\n' => out += "\\n",
+            '\t' => out += "\\t",
+            '\r' => out += "\\r",
+            '\x1b' => out += "\\e",
+            '\\' | '\'' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+fn escape_double_quoted(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if "\\\"$`".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// `$name`, or `${name}` when the following character would extend the name
+fn parameter(name: &str, next: Option<char>) -> String {
+    let is_name = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let extends = next.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '[');
+    let subscript = name.contains('[');
+    if name.len() > 1 && name.chars().all(|c| c.is_ascii_digit())
+        || (is_name && extends)
+        || subscript
+    {
+        format!("${{{name}}}")
+    } else {
+        format!("${name}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::parse::parse;
+
+    use super::*;
+
+    fn fmt(input: &str) -> String {
+        format(&parse(input).unwrap())
+    }
+
+    #[test]
+    fn spacing() {
+        assert_eq!(fmt("a|b&&c  ||d"), "a | b && c || d\n");
+        assert_eq!(
+            fmt("!  a|b; time  -p a; ! time 'time'"),
+            "! a | b\ntime -p a\ntime ! 'time'\n"
+        );
+        assert_eq!(fmt("echo  hi>out 2>>err <in"), "echo hi >out 2>>err <in\n");
+        assert_eq!(fmt("a;b &"), "a\nb &\n");
+        assert_eq!(fmt("A=1  B='x y' cmd"), "A=1 B='x y' cmd\n");
+        assert_eq!(fmt(">file"), ">file\n");
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(fmt("echo 'hello' \"world\" \\a"), "echo hello world a\n");
+        assert_eq!(fmt("echo 'a b' \"it's\" \\*"), "echo 'a b' \"it's\" '*'\n");
+        assert_eq!(
+            fmt("echo \"$HOME\"/x \"${a}b\" $a'b'"),
+            "echo \"$HOME\"/x \"${a}b\" ${a}b\n"
+        );
+        assert_eq!(fmt("echo ${a[1]} ${a}[1]"), "echo ${a[1]} ${a}[1]\n");
+        assert_eq!(fmt("'if' \\# '~'"), "'if' '#' '~'\n");
+        assert_eq!(fmt("'A=1' B=2"), "\\A=1 B=2\n");
+        assert_eq!(
+            fmt("$'a b' $'\\t\\x1b\\'\\u0001'"),
+            "'a b' $'\\t\\e\\'\\u0001'\n"
+        );
+        assert_eq!(fmt("echo \"\\$a \\\\ $b\""), "echo \"\\$a \\\\ $b\"\n");
+        assert_eq!(
+            fmt("echo $\"x\" $\"{a}\" a$\\b $\"\" $\"/\""),
+            "echo $\"x\" $\"{a}\" a$\\b $\"\" $/\n"
+        );
+        assert_eq!(fmt("echo \\$\"x\" \"$\"x"), "echo '$'x '$'x\n");
+    }
+
+    #[test]
+    fn round_trip() {
+        let inputs = [
+            "echo $\"x\" $\"{a}\" $\"?\" a$\"b c\" $\\x $\"\"",
+            "echo \"$HOME\"/x 'a b' $'\\t' ${a}b \"${a}b\"",
+            "for x in a 'b c'; do echo $x; done >out",
+        ];
+        for input in inputs {
+            let list = parse(input).unwrap();
+            assert_eq!(parse(&format(&list)), Ok(list), "{input}");
+        }
+    }
+
+    #[test]
+    fn compound_commands() {
+        assert_eq!(
+            fmt("if a;then b;elif c; then d; else e;fi"),
+            "if a; then\n  b\nelif c; then\n  d\nelse\n  e\nfi\n"
+        );
+        assert_eq!(
+            fmt("for x in 1 2; do while a; do { b; } > out; done; done"),
+            "for x in 1 2; do\n  while a; do\n    {\n      b\n    } >out\n  done\ndone\n"
+        );
+        assert_eq!(
+            fmt("case $x in a|b) one;; *) esac"),
+            "case $x in\n  a | b)\n    one\n    ;;\n  *)\n    ;;\nesac\n"
+        );
+        assert_eq!(fmt("(cd /; ls) | wc"), "(\n  cd /\n  ls\n) | wc\n");
+        assert_eq!(
+            fmt("[[ ! ( -f $a||$b<c ) && ( x =~ ^(a|b)+$ ) ]]"),
+            "[[ ! ( -f $a || $b < c ) && x =~ ^(a|b)+$ ]]\n"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            fmt("# head\nif a # why\nthen\n b # inline\n   # tail\nfi\n# end"),
+            "# head\nif\n  a # why\nthen\n  b # inline\n  # tail\nfi\n# end\n"
+        );
+    }
+
+    #[test]
+    fn idempotent() {
+        let script = "# setup\nfor f in *.rs 'a b'; do\n  case \"$f\" in\n    a*) echo \"$f\" >>log;;\n  esac\ndone | sort && echo ok";
+        let formatted = fmt(script);
+        assert_eq!(fmt(&formatted), formatted);
+    }
+}
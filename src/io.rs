@@ -1,11 +1,41 @@
 use std::{
     fs::File,
-    io::{self, Read, Write},
-    sync::mpsc::{Receiver, Sender},
+    io::{self, IsTerminal, Read, Write},
+    os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+    process::Stdio,
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use anyhow::Result;
 
+use crate::job::interrupted;
+
+/// Size of the buffer used to copy the output of a child process
+const COPY_BUFFER_SIZE: usize = 8192;
+
+/// Create a pipe, returning its read and write ends.
+///
+/// Both ends are closed on exec, a child only gets the end it is given as
+/// one of its standard streams.
+pub(crate) fn pipe() -> Result<(File, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe only writes two file descriptors into the array
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // SAFETY: both descriptors were just created and nothing else owns them
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    for fd in fds {
+        // SAFETY: fd is open, F_SETFD only changes its flags
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok((File::from(read), File::from(write)))
+}
+
 pub(crate) fn write_stderr(stderr: &mut [PErr], data: &[u8]) -> Result<()> {
     for s in stderr {
         s.consume(data)?;
@@ -23,7 +53,10 @@ pub(crate) fn write_stdout(stdout: &mut [POut], data: &[u8]) -> Result<()> {
 #[derive(Debug)]
 pub(crate) enum PIn {
     File(File),
-    Pipe(Receiver<Vec<u8>>),
+    /// Stdin of the shell, such as its terminal, inherited by child processes
+    Std(File),
+    /// Read end of a pipe, every command of a compound command reads from a duplicate
+    Pipe(File),
     Empty,
 }
 
@@ -31,7 +64,10 @@ pub(crate) enum PIn {
 pub(crate) enum POut {
     File(File),
     Std(io::Stdout),
-    Pipe(Sender<Vec<u8>>),
+    /// Write end of a pipe
+    Pipe(File),
+    /// Channel to a thread writing into a pipe, see [`POut::bridge`]
+    Bridge(Sender<Vec<u8>>),
 }
 
 #[derive(Debug)]
@@ -39,7 +75,45 @@ pub(crate) enum PErr {
     File(File),
     Std(io::Stderr),
     #[allow(dead_code)]
-    Pipe(Sender<Vec<u8>>),
+    Pipe(File),
+}
+
+/// Streams inherited by commands that are not redirected
+#[derive(Debug)]
+pub(crate) struct Io {
+    pub stdin: PIn,
+    pub stdout: Vec<POut>,
+    pub stderr: Vec<PErr>,
+}
+
+impl Io {
+    pub(crate) fn std() -> Io {
+        Io {
+            stdin: PIn::std(),
+            stdout: vec![POut::Std(io::stdout())],
+            stderr: vec![PErr::Std(io::stderr())],
+        }
+    }
+
+    pub(crate) fn try_clone(&self) -> Result<Io> {
+        Ok(Io {
+            stdin: self.stdin.try_clone()?,
+            stdout: self
+                .stdout
+                .iter()
+                .map(POut::try_clone)
+                .collect::<Result<_>>()?,
+            stderr: self
+                .stderr
+                .iter()
+                .map(PErr::try_clone)
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub(crate) fn write_stderr(&self, data: &[u8]) -> Result<()> {
+        write_stderr(&mut self.try_clone()?.stderr, data)
+    }
 }
 
 #[derive(Debug)]
@@ -51,48 +125,240 @@ pub(crate) enum PType {
 }
 
 impl PIn {
-    /// Write all the remaining data into writer
-    pub(crate) fn send_to_writer<W: Write>(&mut self, mut writer: W) -> Result<()> {
+    /// Stdin of the shell, read without buffering so that the rest of the
+    /// input is left to the next command
+    pub(crate) fn std() -> PIn {
+        match io::stdin().as_fd().try_clone_to_owned() {
+            Ok(fd) => PIn::Std(fd.into()),
+            // stdin is closed
+            Err(_) => PIn::Empty,
+        }
+    }
+
+    /// Stdin of a child process
+    pub(crate) fn into_stdio(self) -> Stdio {
         match self {
-            PIn::File(file) => {
-                let mut data = Vec::new();
-                file.read_to_end(&mut data)?;
-                write_all_and_flush(&mut writer, &data)?;
-                Ok(())
-            }
-            PIn::Pipe(receiver) => {
-                while let Ok(data) = receiver.recv() {
-                    write_all_and_flush(&mut writer, &data)?;
+            PIn::File(file) | PIn::Pipe(file) => file.into(),
+            // the child gets the terminal itself, so that it can change its modes
+            PIn::Std(_) => Stdio::inherit(),
+            PIn::Empty => Stdio::null(),
+        }
+    }
+
+    /// Read a single byte, `None` at the end of the input.
+    ///
+    /// Reading a byte at a time leaves the rest of the input to the next
+    /// command reading from the same stream.
+    pub(crate) fn read_byte(&mut self) -> Result<Option<u8>> {
+        let file = match self {
+            PIn::File(file) | PIn::Pipe(file) | PIn::Std(file) => file,
+            PIn::Empty => return Ok(None),
+        };
+        let mut byte = [0; 1];
+        loop {
+            match file.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                // Ctrl-C ends the input
+                Err(err) if err.kind() == io::ErrorKind::Interrupted && interrupted() => {
+                    return Ok(None)
                 }
-                Ok(())
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Wait for input to read, or the end of the input, for at most `timeout`.
+    /// Returns whether the input is ready.
+    pub(crate) fn poll(&self, timeout: Duration) -> Result<bool> {
+        let Some(fd) = self.as_raw_fd() else {
+            return Ok(true);
+        };
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_nanos().div_ceil(1_000_000);
+        let millis = libc::c_int::try_from(millis).unwrap_or(libc::c_int::MAX);
+        loop {
+            // SAFETY: pollfd is a valid array of one element
+            match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+                -1 => return Err(io::Error::last_os_error().into()),
+                ready => return Ok(ready > 0),
             }
-            PIn::Empty => Ok(()),
         }
     }
+
+    pub(crate) fn as_raw_fd(&self) -> Option<RawFd> {
+        match self {
+            PIn::File(file) | PIn::Pipe(file) | PIn::Std(file) => Some(file.as_raw_fd()),
+            PIn::Empty => None,
+        }
+    }
+
+    pub(crate) fn is_terminal(&self) -> bool {
+        match self {
+            PIn::File(file) | PIn::Pipe(file) | PIn::Std(file) => file.is_terminal(),
+            PIn::Empty => false,
+        }
+    }
+
+    /// Read all the remaining input
+    pub(crate) fn read_to_end(&mut self) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        if let PIn::File(file) | PIn::Pipe(file) | PIn::Std(file) = self {
+            file.read_to_end(&mut data)?;
+        }
+        Ok(data)
+    }
+
+    pub(crate) fn try_clone(&self) -> Result<PIn> {
+        match self {
+            PIn::File(file) => Ok(PIn::File(file.try_clone()?)),
+            PIn::Pipe(file) => Ok(PIn::Pipe(file.try_clone()?)),
+            PIn::Std(file) => Ok(PIn::Std(file.try_clone()?)),
+            PIn::Empty => Ok(PIn::Empty),
+        }
+    }
+}
+
+/// Destination of the output of a command
+pub(crate) trait Output: Send + 'static {
+    /// Get all data and send it into the destination
+    fn consume(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Stream a child process can write into directly, if there is one
+    fn stdio(&self) -> Result<Option<Stdio>>;
 }
 
 impl POut {
-    /// Get all data and send into `POut`
+    /// Write into a pipe from a thread.
+    ///
+    /// Used when a builtin writes into a pipe, as the shell would block on a
+    /// full pipe if the reader is itself a builtin that only runs afterwards.
+    pub(crate) fn bridge(mut pipe: File) -> POut {
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+        thread::spawn(move || {
+            for data in receiver {
+                // the reader may have exited
+                if pipe.write_all(&data).is_err() {
+                    break;
+                }
+            }
+        });
+        POut::Bridge(sender)
+    }
+
+    pub(crate) fn try_clone(&self) -> Result<POut> {
+        match self {
+            POut::File(file) => Ok(POut::File(file.try_clone()?)),
+            POut::Std(_) => Ok(POut::Std(io::stdout())),
+            POut::Pipe(file) => Ok(POut::Pipe(file.try_clone()?)),
+            POut::Bridge(sender) => Ok(POut::Bridge(sender.clone())),
+        }
+    }
+}
+
+impl Output for POut {
     fn consume(&mut self, data: &[u8]) -> Result<()> {
         match self {
             POut::Std(stdout) => write_all_and_flush(stdout, data)?,
-            POut::File(file) => write_all_and_flush(file, data)?,
-            POut::Pipe(sender) => sender.send(data.to_vec())?,
+            POut::File(file) | POut::Pipe(file) => write_all_and_flush(file, data)?,
+            POut::Bridge(sender) => sender.send(data.to_vec())?,
         }
         Ok(())
     }
+
+    fn stdio(&self) -> Result<Option<Stdio>> {
+        match self {
+            POut::File(file) | POut::Pipe(file) => Ok(Some(file.try_clone()?.into())),
+            POut::Std(_) => Ok(Some(Stdio::inherit())),
+            POut::Bridge(_) => Ok(None),
+        }
+    }
 }
 
 impl PErr {
-    /// Get all data and send into `PErr`
+    pub(crate) fn try_clone(&self) -> Result<PErr> {
+        match self {
+            PErr::File(file) => Ok(PErr::File(file.try_clone()?)),
+            PErr::Std(_) => Ok(PErr::Std(io::stderr())),
+            PErr::Pipe(file) => Ok(PErr::Pipe(file.try_clone()?)),
+        }
+    }
+}
+
+impl Output for PErr {
     fn consume(&mut self, data: &[u8]) -> Result<()> {
         match self {
             PErr::Std(stderr) => write_all_and_flush(stderr, data)?,
-            PErr::File(file) => write_all_and_flush(file, data)?,
-            PErr::Pipe(sender) => sender.send(data.to_vec())?,
+            PErr::File(file) | PErr::Pipe(file) => write_all_and_flush(file, data)?,
         }
         Ok(())
     }
+
+    fn stdio(&self) -> Result<Option<Stdio>> {
+        match self {
+            PErr::File(file) | PErr::Pipe(file) => Ok(Some(file.try_clone()?.into())),
+            PErr::Std(_) => Ok(Some(Stdio::inherit())),
+        }
+    }
+}
+
+/// Stream of a child process writing into `outputs`.
+///
+/// A single file descriptor is given to the child, otherwise the outputs are
+/// returned to be fed by [`copy_output`] from a pipe.
+pub(crate) fn child_output<O: Output>(outputs: Vec<O>) -> Result<(Stdio, Option<Vec<O>>)> {
+    if let [output] = &outputs[..] {
+        if let Some(stdio) = output.stdio()? {
+            return Ok((stdio, None));
+        }
+    }
+    Ok((Stdio::piped(), Some(outputs)))
+}
+
+/// Outputs a forked copy of the shell writes into.
+///
+/// The threads of the shell do not run in the child, so when one of the
+/// outputs is a [`POut::Bridge`] the child gets a pipe, copied into the
+/// outputs by the returned thread.
+pub(crate) fn forked_output(outputs: Vec<POut>) -> Result<(Vec<POut>, Option<JoinHandle<()>>)> {
+    if !outputs
+        .iter()
+        .any(|output| matches!(output, POut::Bridge(_)))
+    {
+        return Ok((outputs, None));
+    }
+    let (reader, writer) = pipe()?;
+    Ok((vec![POut::Pipe(writer)], Some(copy_output(reader, outputs))))
+}
+
+/// Copy everything read from `reader` into every output, from a thread
+pub(crate) fn copy_output<R, O>(mut reader: R, mut outputs: Vec<O>) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    O: Output,
+{
+    thread::spawn(move || {
+        let mut buf = [0; COPY_BUFFER_SIZE];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    for output in &mut outputs {
+                        // keep reading so that the child does not block
+                        let _ = output.consume(&buf[..n]);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+    })
 }
 
 fn write_all_and_flush<W: Write>(w: &mut W, data: &[u8]) -> Result<()> {
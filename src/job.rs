@@ -0,0 +1,560 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use anyhow::Result;
+
+/// Status of a job stopped by a signal, as reported by `$?`
+pub(crate) const STOPPED_STATUS: i32 = 128 + libc::SIGTSTP;
+
+/// Signals ignored by an interactive shell, so that only the foreground job
+/// is suspended by the terminal
+const JOB_CONTROL_SIGNALS: [libc::c_int; 3] = [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+/// Terminal of an interactive shell, set up by [`init_job_control`]
+static TERMINAL: OnceLock<Terminal> = OnceLock::new();
+
+struct Terminal {
+    /// Duplicate of the shell's stdin, still open in a child process until it
+    /// executes a command, when stdin may already be redirected
+    fd: libc::c_int,
+    /// Modes when the shell started, restored when a job gives the terminal
+    /// back as full screen programs may leave it in raw mode
+    modes: libc::termios,
+}
+
+/// Status of a command interrupted by Ctrl-C
+pub(crate) const INTERRUPTED_STATUS: i32 = 128 + libc::SIGINT;
+
+/// Set when SIGINT is received, until the next command line is executed
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Process of a job, or a command run by the shell itself which has no pid
+#[derive(Debug, Clone)]
+pub(crate) struct Process {
+    pub pid: Option<libc::pid_t>,
+    /// Exit status, once the process has exited
+    pub status: Option<i32>,
+    /// Signal that terminated the process, if it did not exit by itself
+    pub signal: Option<libc::c_int>,
+    pub stopped: bool,
+}
+
+impl Process {
+    pub fn new(pid: libc::pid_t) -> Process {
+        Process {
+            pid: Some(pid),
+            status: None,
+            signal: None,
+            stopped: false,
+        }
+    }
+
+    pub fn finished(status: i32) -> Process {
+        Process {
+            pid: None,
+            status: Some(status),
+            signal: None,
+            stopped: false,
+        }
+    }
+
+    /// Record the termination of the process by `signal`, its status is
+    /// 128 plus the signal number
+    fn terminate(&mut self, signal: libc::c_int) {
+        self.status = Some(128 + signal);
+        self.signal = Some(signal);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+/// Pipeline started by the shell
+#[derive(Debug, Clone)]
+pub(crate) struct Job {
+    pub id: usize,
+    /// Process group of the job, if job control is enabled
+    pub pgid: Option<libc::pid_t>,
+    pub processes: Vec<Process>,
+    /// Command line shown by `jobs`
+    pub text: String,
+    pub state: JobState,
+}
+
+impl Job {
+    pub fn new(text: String, pgid: Option<libc::pid_t>, processes: Vec<Process>) -> Job {
+        let mut job = Job {
+            id: 0,
+            pgid,
+            processes,
+            text,
+            state: JobState::Running,
+        };
+        job.update_state();
+        job
+    }
+
+    /// Exit status of every process, 0 for those still running
+    pub fn statuses(&self) -> Vec<i32> {
+        self.processes
+            .iter()
+            .map(|process| process.status.unwrap_or_default())
+            .collect()
+    }
+
+    /// Whether one of the processes was terminated by Ctrl-C, rather than
+    /// exiting with the same status
+    pub fn interrupted(&self) -> bool {
+        self.processes
+            .iter()
+            .any(|process| process.signal == Some(libc::SIGINT))
+    }
+
+    /// Pid of the last process
+    pub fn last_pid(&self) -> Option<libc::pid_t> {
+        self.processes.iter().rev().find_map(|process| process.pid)
+    }
+
+    /// Wait until every process exits or one of them stops
+    pub fn wait(&mut self) -> Result<()> {
+        for process in &mut self.processes {
+            let Some(pid) = process.pid.filter(|_| process.status.is_none()) else {
+                continue;
+            };
+            match wait_pid(pid, libc::WUNTRACED)? {
+                Some(WaitStatus::Exited(status)) => process.status = Some(status),
+                Some(WaitStatus::Signaled(signal)) => process.terminate(signal),
+                Some(WaitStatus::Stopped) => {
+                    process.stopped = true;
+                    break;
+                }
+                Some(WaitStatus::Continued) | None => {}
+            }
+        }
+        self.update_state();
+        Ok(())
+    }
+
+    /// Collect the processes that exited, stopped or continued, without blocking
+    pub fn poll(&mut self) -> Result<()> {
+        for process in &mut self.processes {
+            let Some(pid) = process.pid.filter(|_| process.status.is_none()) else {
+                continue;
+            };
+            let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+            match wait_pid(pid, flags)? {
+                Some(WaitStatus::Exited(status)) => process.status = Some(status),
+                Some(WaitStatus::Signaled(signal)) => process.terminate(signal),
+                Some(WaitStatus::Stopped) => process.stopped = true,
+                Some(WaitStatus::Continued) => process.stopped = false,
+                None => {}
+            }
+        }
+        self.update_state();
+        Ok(())
+    }
+
+    /// Send SIGCONT to every process of the job
+    pub fn resume(&mut self) -> Result<()> {
+        self.signal(libc::SIGCONT)?;
+        for process in &mut self.processes {
+            process.stopped = false;
+        }
+        self.update_state();
+        Ok(())
+    }
+
+    pub fn signal(&self, signal: libc::c_int) -> Result<()> {
+        let pids = match self.pgid {
+            Some(pgid) => vec![-pgid],
+            None => self.running_pids().collect(),
+        };
+        for pid in pids {
+            // SAFETY: kill has no memory safety requirements
+            if unsafe { libc::kill(pid, signal) } != 0 {
+                let err = io::Error::last_os_error();
+                // the processes may have exited already
+                if err.raw_os_error() != Some(libc::ESRCH) {
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn running_pids(&self) -> impl Iterator<Item = libc::pid_t> + '_ {
+        self.processes
+            .iter()
+            .filter(|process| process.status.is_none())
+            .filter_map(|process| process.pid)
+    }
+
+    fn update_state(&mut self) {
+        let pending = self.processes.iter().filter(|p| p.status.is_none());
+        self.state = match pending.clone().count() {
+            0 => JobState::Done,
+            _ if pending.clone().any(|p| p.stopped) => JobState::Stopped,
+            _ => JobState::Running,
+        };
+    }
+
+    /// Line printed by `jobs`, `mark` is `+` for the current job and `-` for
+    /// the previous one
+    pub fn describe(&self, mark: char, pid: bool) -> String {
+        let state = match self.state {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => "Stopped".to_string(),
+            JobState::Done => match self.statuses().last() {
+                Some(0) | None => "Done".to_string(),
+                Some(status) => format!("Exit {status}"),
+            },
+        };
+        let pid = match (pid, self.processes.iter().find_map(|p| p.pid)) {
+            (true, Some(pid)) => format!("{pid} "),
+            _ => String::new(),
+        };
+        let background = match self.state {
+            JobState::Running => " &",
+            _ => "",
+        };
+        format!(
+            "[{}]{mark}  {pid}{state:<24}{}{background}",
+            self.id, self.text
+        )
+    }
+}
+
+/// Jobs in the background or stopped
+#[derive(Debug, Default, Clone)]
+pub(crate) struct JobTable {
+    jobs: Vec<Job>,
+    /// Ids from the least to the most recently used job, the last one is the
+    /// current job `%+` and the one before the previous job `%-`
+    recent: Vec<usize>,
+}
+
+impl JobTable {
+    /// Add a job, returning its id. A job without an id gets the next one.
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or_default() + 1;
+        }
+        let id = job.id;
+        self.recent.push(id);
+        self.jobs.push(job);
+        self.jobs.sort_by_key(|job| job.id);
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        self.recent.retain(|&recent| recent != id);
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// Make the job the current one
+    pub fn touch(&mut self, id: usize) {
+        self.recent.retain(|&recent| recent != id);
+        self.recent.push(id);
+    }
+
+    /// `+` for the current job, `-` for the previous one
+    pub fn mark(&self, id: usize) -> char {
+        match self.recent.iter().rev().position(|&recent| recent == id) {
+            Some(0) => '+',
+            Some(1) => '-',
+            _ => ' ',
+        }
+    }
+
+    /// Collect the state changes of every job
+    pub fn poll(&mut self) -> Result<()> {
+        for job in &mut self.jobs {
+            job.poll()?;
+        }
+        Ok(())
+    }
+
+    /// Remove the jobs that are done, returning their `jobs` lines
+    pub fn remove_done(&mut self) -> Vec<String> {
+        let done: Vec<usize> = self
+            .jobs
+            .iter()
+            .filter(|job| job.state == JobState::Done)
+            .map(|job| job.id)
+            .collect();
+        done.into_iter()
+            .filter_map(|id| {
+                let mark = self.mark(id);
+                self.remove(id).map(|job| job.describe(mark, false))
+            })
+            .collect()
+    }
+
+    /// Id of the job a job spec refers to, the current job if there is none.
+    ///
+    /// `%%`, `%+` and `%` are the current job, `%-` the previous one, `%n`
+    /// job number `n`, `%string` the job whose command starts with `string`
+    /// and `%?string` the one whose command contains it.
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let spec = spec.unwrap_or("%+");
+        let nth_recent = |n: usize| {
+            self.recent
+                .iter()
+                .rev()
+                .nth(n)
+                .copied()
+                .ok_or_else(|| format!("{spec}: no such job"))
+        };
+        let matching = |found: Vec<&Job>| match found[..] {
+            [job] => Ok(job.id),
+            [] => Err(format!("{spec}: no such job")),
+            _ => Err(format!("{spec}: ambiguous job spec")),
+        };
+
+        match spec.strip_prefix('%').unwrap_or(spec) {
+            "" | "%" | "+" => nth_recent(0),
+            "-" => nth_recent(1),
+            n if n.bytes().all(|b| b.is_ascii_digit()) => n
+                .parse()
+                .ok()
+                .filter(|&id| self.jobs.iter().any(|job| job.id == id))
+                .ok_or_else(|| format!("{spec}: no such job")),
+            s => match s.strip_prefix('?') {
+                Some(s) => matching(self.jobs.iter().filter(|j| j.text.contains(s)).collect()),
+                None => matching(self.jobs.iter().filter(|j| j.text.starts_with(s)).collect()),
+            },
+        }
+    }
+}
+
+enum WaitStatus {
+    Exited(i32),
+    /// Terminated by the signal
+    Signaled(libc::c_int),
+    Stopped,
+    Continued,
+}
+
+/// Wait for a change of state of `pid`, `None` if there is none with `WNOHANG`
+fn wait_pid(pid: libc::pid_t, flags: libc::c_int) -> Result<Option<WaitStatus>> {
+    let mut status = 0;
+    loop {
+        // SAFETY: waitpid only writes into status
+        match unsafe { libc::waitpid(pid, &mut status, flags) } {
+            0 => return Ok(None),
+            -1 => {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // already reaped, its status is lost
+                    Some(libc::ECHILD) => return Ok(Some(WaitStatus::Exited(0))),
+                    _ => return Err(err.into()),
+                }
+            }
+            _ => break,
+        }
+    }
+
+    Ok(Some(if libc::WIFSTOPPED(status) {
+        WaitStatus::Stopped
+    } else if libc::WIFCONTINUED(status) {
+        WaitStatus::Continued
+    } else if libc::WIFSIGNALED(status) {
+        WaitStatus::Signaled(libc::WTERMSIG(status))
+    } else {
+        WaitStatus::Exited(libc::WEXITSTATUS(status))
+    }))
+}
+
+/// Put the shell in its own process group in control of the terminal, and
+/// ignore the signals that would stop it
+pub(crate) fn init_job_control() -> Result<()> {
+    for signal in JOB_CONTROL_SIGNALS {
+        // SAFETY: ignoring a signal does not involve a handler
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }
+    // SAFETY: setpgid and getpid have no memory safety requirements,
+    // setpgid fails harmlessly if the shell is a session leader
+    unsafe { libc::setpgid(0, 0) };
+
+    // SAFETY: tcgetattr only writes into the struct it is given, the
+    // duplicate is closed on exec
+    let mut modes: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut modes) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let fd = unsafe { libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let _ = TERMINAL.set(Terminal { fd, modes });
+    give_terminal(unsafe { libc::getpgrp() })
+}
+
+/// Catch SIGINT in an interactive shell, which stops running commands rather
+/// than exiting.
+///
+/// The handler is not restarting, so a builtin blocked reading the terminal
+/// returns. Child processes get the default action back when they execute.
+pub(crate) fn catch_interrupt() -> Result<()> {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    // SAFETY: the handler only stores into an atomic, which is
+    // async-signal-safe, and sigaction reads the struct it is given
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// Whether Ctrl-C interrupted the commands being executed
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Stop executing commands, as if SIGINT was received
+pub(crate) fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Forget an interruption before executing a new command line
+pub(crate) fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// Terminate the process by SIGINT, so that its parent sees that it was
+/// interrupted rather than that it exited with [`INTERRUPTED_STATUS`]
+pub(crate) fn exit_interrupted() -> ! {
+    // SAFETY: restoring the default action does not involve a handler, and
+    // raise has no memory safety requirements
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+        libc::raise(libc::SIGINT);
+    }
+    std::process::exit(INTERRUPTED_STATUS)
+}
+
+/// Restore the default action of the signals ignored by the shell, in a
+/// child process before it executes a command.
+///
+/// Only async-signal-safe functions are called.
+pub(crate) fn reset_signals() -> io::Result<()> {
+    for signal in JOB_CONTROL_SIGNALS {
+        // SAFETY: restoring the default action does not involve a handler
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+    Ok(())
+}
+
+/// Put a child process of a foreground job in control of the terminal before
+/// it executes a command, as it may use the terminal before the shell gives
+/// it to the job.
+///
+/// Only async-signal-safe functions are called.
+pub(crate) fn claim_terminal() -> io::Result<()> {
+    if let Some(terminal) = TERMINAL.get() {
+        // SAFETY: tcsetpgrp and getpgrp have no memory safety requirements,
+        // SIGTTOU is still ignored as in the shell
+        unsafe { libc::tcsetpgrp(terminal.fd, libc::getpgrp()) };
+    }
+    Ok(())
+}
+
+/// Make `pgid` the foreground process group of the terminal
+pub(crate) fn give_terminal(pgid: libc::pid_t) -> Result<()> {
+    let fd = TERMINAL
+        .get()
+        .map_or(libc::STDIN_FILENO, |terminal| terminal.fd);
+    // SAFETY: tcsetpgrp has no memory safety requirements
+    match unsafe { libc::tcsetpgrp(fd, pgid) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error().into()),
+    }
+}
+
+/// Give the terminal back to the shell, with the modes it had at startup
+pub(crate) fn take_terminal() -> Result<()> {
+    // SAFETY: getpgrp cannot fail
+    give_terminal(unsafe { libc::getpgrp() })?;
+    if let Some(terminal) = TERMINAL.get() {
+        // SAFETY: the modes were read from the same terminal
+        unsafe { libc::tcsetattr(terminal.fd, libc::TCSADRAIN, &terminal.modes) };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn job(text: &str) -> Job {
+        Job::new(text.into(), None, vec![Process::finished(0)])
+    }
+
+    #[test]
+    fn job_specs() {
+        let mut jobs = JobTable::default();
+        assert_eq!(jobs.find(None), Err("%+: no such job".into()));
+        jobs.add(job("sleep 10"));
+        jobs.add(job("vim notes"));
+        jobs.add(job("sleep 20"));
+        assert_eq!(jobs.find(None), Ok(3));
+        assert_eq!(jobs.find(Some("%-")), Ok(2));
+        assert_eq!(jobs.find(Some("%1")), Ok(1));
+        assert_eq!(jobs.find(Some("%vim")), Ok(2));
+        assert_eq!(jobs.find(Some("%?20")), Ok(3));
+        assert_eq!(
+            jobs.find(Some("%sleep")),
+            Err("%sleep: ambiguous job spec".into())
+        );
+        assert_eq!(jobs.find(Some("%4")), Err("%4: no such job".into()));
+
+        jobs.touch(1);
+        assert_eq!((jobs.mark(1), jobs.mark(3), jobs.mark(2)), ('+', '-', ' '));
+        jobs.remove(1);
+        assert_eq!(jobs.find(Some("%%")), Ok(3));
+        assert_eq!(jobs.add(job("ls")), 4);
+    }
+
+    #[test]
+    fn describe() {
+        let mut job = job("sleep 10");
+        job.id = 2;
+        assert_eq!(
+            job.describe('+', false),
+            "[2]+  Done                    sleep 10"
+        );
+        job.processes = vec![Process::new(42)];
+        job.update_state();
+        assert_eq!(
+            job.describe('-', true),
+            "[2]-  42 Running                 sleep 10 &"
+        );
+    }
+}
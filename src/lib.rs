@@ -1,10 +1,16 @@
+pub mod ast;
 mod command;
 mod complete;
+mod format;
 mod io;
+mod job;
 mod parse;
 mod shell;
+mod state;
 pub(crate) mod utils;
 
-pub use shell::run_shell;
+pub use format::format;
+pub use parse::{parse, ParseError};
+pub use shell::{run_shell, ShellConfig};
 
 pub const HIST_FILE: &str = "./history";
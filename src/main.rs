@@ -1,7 +1,27 @@
-use anyhow::Result;
-use codecrafters_shell::run_shell;
+use anyhow::{Context, Result};
+use codecrafters_shell::{format, parse, run_shell, ShellConfig};
 
 fn main() -> Result<()> {
-    run_shell()?;
+    let config = match ShellConfig::parse(std::env::args()) {
+        Ok(config) => config,
+        Err(err) => {
+            let name = std::env::args().next().unwrap_or_default();
+            eprintln!("{name}: {err}");
+            std::process::exit(2);
+        }
+    };
+    if let Some(path) = &config.format {
+        return format_file(path);
+    }
+
+    let status = run_shell(config)?;
+    std::process::exit(status);
+}
+
+/// Print the canonical form of a script
+fn format_file(path: &str) -> Result<()> {
+    let input = std::fs::read_to_string(path).with_context(|| path.to_string())?;
+    let list = parse(&input).with_context(|| path.to_string())?;
+    print!("{}", format(&list));
     Ok(())
 }
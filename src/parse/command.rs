@@ -1,44 +1,140 @@
 use winnow::{
-    ascii::multispace0,
-    combinator::{alt, delimited, opt, preceded, repeat},
-    token::{any, take_till, take_until},
+    ascii::digit1,
+    combinator::{alt, cut_err, delimited, opt, preceded, repeat, terminated},
+    stream::AsChar,
+    token::{any, one_of, take_till, take_until, take_while},
     ModalResult, Parser,
 };
 
-use super::{CommandToken, Stream};
+use crate::ast::{Word, WordPart};
 
-pub(super) fn command_token(stream: &mut Stream) -> ModalResult<CommandToken> {
-    preceded(
-        multispace0,
-        repeat(
-            1..,
-            alt((single_quote, double_quote, no_quote)),
-        )
-        .fold(String::new, |acc, item| acc + &item)
-        .map(CommandToken),
+use super::Stream;
+
+/// Characters ending an unquoted part of a word
+const NO_QUOTE_END: &str = " \t\r\n\\'\"|&;<>()$";
+
+pub(super) fn word_token(stream: &mut Stream) -> ModalResult<Word> {
+    repeat(
+        1..,
+        alt((
+            single_quote.map(|s| vec![WordPart::SingleQuoted(s)]),
+            double_quote.map(|parts| vec![WordPart::DoubleQuoted(parts)]),
+            no_quote,
+        )),
     )
+    .fold(Vec::new, |mut acc, parts: Vec<WordPart>| {
+        for part in parts {
+            push_part(&mut acc, part);
+        }
+        acc
+    })
+    .map(Word)
     .parse_next(stream)
 }
 
+/// Push `part` into `parts`, merging adjacent literals and dropping empty ones
+fn push_part(parts: &mut Vec<WordPart>, part: WordPart) {
+    match (parts.last_mut(), part) {
+        (_, WordPart::Literal(s)) if s.is_empty() => {}
+        (Some(WordPart::Literal(last)), WordPart::Literal(s)) => last.push_str(&s),
+        (_, part) => parts.push(part),
+    }
+}
+
 fn single_quote(stream: &mut Stream) -> ModalResult<String> {
-    delimited('\'', take_until(1.., "'").map(String::from), '\'').parse_next(stream)
+    delimited('\'', take_until(0.., "'").map(String::from), '\'').parse_next(stream)
+}
+
+/// `$'...'`, decoding backslash escapes like C strings do
+fn ansi_c_quote(stream: &mut Stream) -> ModalResult<String> {
+    preceded(
+        "$'",
+        cut_err(terminated(
+            repeat(
+                0..,
+                alt((
+                    take_till(1.., ['\'', '\\']).map(String::from),
+                    preceded('\\', ansi_c_escape),
+                )),
+            )
+            .fold(String::new, |acc, s| acc + &s),
+            '\'',
+        )),
+    )
+    // like bash, a NUL byte ends the string
+    .map(|s: String| match s.split_once('\0') {
+        Some((s, _)) => s.to_string(),
+        None => s,
+    })
+    .parse_next(stream)
 }
 
-fn double_quote(stream: &mut Stream) -> ModalResult<String> {
+/// Character after a backslash in `$'...'`, unknown escapes are kept as is
+fn ansi_c_escape(stream: &mut Stream) -> ModalResult<String> {
+    let c = any.parse_next(stream)?;
+    let byte = |digits: &str, radix| u32::from_str_radix(digits, radix).unwrap_or_default() & 0xff;
+    let code = match c {
+        'n' => '\n'.into(),
+        't' => '\t'.into(),
+        'r' => '\r'.into(),
+        'a' => 0x07,
+        'b' => 0x08,
+        'e' | 'E' => 0x1b,
+        'f' => 0x0c,
+        'v' => 0x0b,
+        '\\' | '\'' | '"' | '?' => c.into(),
+        'x' | 'u' | 'U' => {
+            let max = match c {
+                'x' => 2,
+                'u' => 4,
+                _ => 8,
+            };
+            match opt(take_while(1..=max, AsChar::is_hex_digit)).parse_next(stream)? {
+                Some(digits) if c == 'x' => byte(digits, 16),
+                Some(digits) => u32::from_str_radix(digits, 16).unwrap_or_default(),
+                None => return Ok(format!("\\{c}")),
+            }
+        }
+        // `\0NNN` or `\NNN`, in octal
+        '0'..='7' => {
+            let max = if c == '0' { 3 } else { 2 };
+            let digits = take_while(0..=max, '0'..='7').parse_next(stream)?;
+            byte(&format!("{c}{digits}"), 8)
+        }
+        'c' => any.parse_next(stream)? as u32 & 0x1f,
+        c => return Ok(format!("\\{c}")),
+    };
+    Ok(char::from_u32(code)
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+        .to_string())
+}
+
+fn double_quote(stream: &mut Stream) -> ModalResult<Vec<WordPart>> {
     delimited(
         '"',
-        repeat(0.., double_quote_inner)
-            .fold(String::new, |acc, item| acc + &item)
-            .verify(|s: &str| !s.is_empty()),
+        repeat(
+            0..,
+            alt((
+                dollar,
+                line_continuation,
+                double_quote_inner.map(WordPart::Literal),
+            )),
+        )
+        .fold(Vec::new, |mut acc, part| {
+            push_part(&mut acc, part);
+            acc
+        }),
         '"',
     )
     .parse_next(stream)
 }
 
 fn double_quote_inner(stream: &mut Stream) -> ModalResult<String> {
-    let token = take_till(0.., |c: char| "\"\\".contains(c)).map(String::from);
-    let backslash = opt(alt(((preceded("\\", any)).map(|c| match c {
-        '$' | '`' | '\"' | '\\' | '\n' => c.to_string(),
+    let token = take_till(0.., |c: char| "\"\\$".contains(c)).map(String::from);
+    // a backslash before a newline is a line continuation
+    let escaped = any.verify(|&c| c != '\n');
+    let backslash = opt(alt(((preceded("\\", escaped)).map(|c| match c {
+        '$' | '`' | '\"' | '\\' => c.to_string(),
         c => format!("\\{c}"),
     }),)));
 
@@ -51,171 +147,237 @@ fn double_quote_inner(stream: &mut Stream) -> ModalResult<String> {
         .parse_next(stream)
 }
 
-fn no_quote(stream: &mut Stream) -> ModalResult<String> {
-    repeat(0.., no_quote_inner)
-        .fold(String::new, |acc, item| acc + &item)
-        .verify(|s: &str| !s.is_empty())
+fn no_quote(stream: &mut Stream) -> ModalResult<Vec<WordPart>> {
+    repeat(1.., no_quote_inner)
+        .fold(Vec::new, |mut acc, part| {
+            push_part(&mut acc, part);
+            acc
+        })
         .parse_next(stream)
 }
 
-fn no_quote_inner(stream: &mut Stream) -> ModalResult<String> {
-    let token = take_till(0.., |c: char| " \t\r\n\\\'\"".contains(c)).map(String::from);
-    let backslash = opt(preceded("\\", any));
+fn no_quote_inner(stream: &mut Stream) -> ModalResult<WordPart> {
+    alt((
+        take_till(1.., |c: char| NO_QUOTE_END.contains(c))
+            .map(|s: &str| WordPart::Literal(s.to_string())),
+        line_continuation,
+        preceded("\\", any).map(WordPart::Escaped),
+        ansi_c_quote.map(WordPart::AnsiCQuoted),
+        dollar,
+    ))
+    .parse_next(stream)
+}
 
-    (token, backslash)
-        .map(|(mut token, ch)| {
-            if let Some(ch) = ch {
-                token.push(ch);
-            };
-            token
-        })
-        .verify(|s: &str| !s.is_empty())
+/// Backslash-newline, removed from the word so that it goes on in the next line
+fn line_continuation(stream: &mut Stream) -> ModalResult<WordPart> {
+    "\\\n"
+        .map(|_| WordPart::Literal(String::new()))
         .parse_next(stream)
 }
 
+/// Parameter expansion, or a literal `$` if no valid name follows
+fn dollar(stream: &mut Stream) -> ModalResult<WordPart> {
+    alt((
+        preceded(
+            '$',
+            alt((
+                preceded('{', cut_err(terminated(braced_parameter_name, '}'))),
+                parameter_name,
+            )),
+        )
+        .map(WordPart::Parameter),
+        '$'.map(|_| WordPart::Literal("$".to_string())),
+    ))
+    .parse_next(stream)
+}
+
+fn parameter_name(stream: &mut Stream) -> ModalResult<String> {
+    alt((
+        (
+            one_of(|c: char| c.is_ascii_alphabetic() || c == '_'),
+            take_while(0.., |c: char| c.is_ascii_alphanumeric() || c == '_'),
+        )
+            .take(),
+        one_of(|c: char| c.is_ascii_digit() || "?#@*$!-".contains(c)).take(),
+    ))
+    .map(String::from)
+    .parse_next(stream)
+}
+
+/// Name inside `${}`, with an optional array subscript like `name[1]` or `name[@]`
+fn braced_parameter_name(stream: &mut Stream) -> ModalResult<String> {
+    alt((
+        digit1.map(String::from),
+        (
+            parameter_name,
+            opt(('[', alt((digit1, "@", "*")), ']').take()),
+        )
+            .map(|(name, subscript)| name + subscript.unwrap_or_default()),
+    ))
+    .parse_next(stream)
+}
+
 #[cfg(test)]
 mod test {
 
     use super::*;
 
+    /// Text of the parts with quotes removed
+    fn text(parts: &[WordPart]) -> String {
+        parts
+            .iter()
+            .map(|part| match part {
+                WordPart::Literal(s) | WordPart::SingleQuoted(s) | WordPart::AnsiCQuoted(s) => {
+                    s.clone()
+                }
+                WordPart::Escaped(c) => c.to_string(),
+                WordPart::DoubleQuoted(parts) => text(parts),
+                WordPart::Parameter(name) => format!("${{{name}}}"),
+            })
+            .collect()
+    }
+
+    fn word(input: &str) -> String {
+        text(&word_token(&mut Stream::new(input)).unwrap().0)
+    }
+
     #[test]
     fn test_single_quote() {
         assert_eq!(
             single_quote.parse_next(&mut Stream::new("'hello'")),
             Ok("hello".to_string())
         );
-        assert!(single_quote
-            .parse_next(&mut Stream::new("'hello"))
-            .is_err());
+        assert!(single_quote.parse_next(&mut Stream::new("'hello")).is_err());
+        assert_eq!(
+            single_quote.parse_next(&mut Stream::new("''")),
+            Ok(String::new())
+        );
     }
 
     #[test]
-    fn test_double_quote() {
-        assert_eq!(
-            double_quote.parse_next(&mut Stream::new("\"hello\"")),
-            Ok("hello".to_string())
-        );
+    fn test_ansi_c_quote() {
+        let ansi_c_quote = |input: &str| ansi_c_quote(&mut Stream::new(input));
+        assert_eq!(ansi_c_quote("$'a\\tb'"), Ok("a\tb".to_string()));
         assert_eq!(
-            double_quote.parse_next(&mut Stream::new("\"hello\\$\"")),
-            Ok("hello$".to_string())
+            ansi_c_quote("$'\\n\\e[0m\\x41\\u00e9\\0101\\101\\\\\\''"),
+            Ok("\n\x1b[0mAéAA\\'".to_string())
         );
         assert_eq!(
-            double_quote.parse_next(&mut Stream::new("\"hello\\`\"")),
-            Ok("hello`".to_string())
+            ansi_c_quote("$'\\q\\xz\\cA'"),
+            Ok("\\q\\xz\x01".to_string())
         );
-        assert_eq!(
-            double_quote.parse_next(&mut Stream::new("\"hello\\\"\"")),
-            Ok("hello\"".to_string())
-        );
-        assert_eq!(
-            double_quote.parse_next(&mut Stream::new("\"hello\\\\\"")),
-            Ok("hello\\".to_string())
-        );
-        assert_eq!(
-            double_quote.parse_next(&mut Stream::new("\"hello\\\n\"")),
-            Ok("hello\n".to_string())
-        );
-        assert!(double_quote
-            .parse_next(&mut Stream::new("\"hello"))
-            .is_err());
-        assert!(double_quote
-            .parse_next(&mut Stream::new("\"hello\\"))
-            .is_err(),);
+        assert_eq!(ansi_c_quote("$'a\\0b'"), Ok("a".to_string()));
+        assert!(ansi_c_quote("$'abc").is_err());
+        assert_eq!(word("$'a b'c\n"), "a bc");
+        assert_eq!(word("-d$'\\t'\n"), "-d\t");
+        assert_eq!(word("\"$'a'\"\n"), "$'a'");
+    }
+
+    #[test]
+    fn test_double_quote() {
+        let double_quote = |input: &str| double_quote(&mut Stream::new(input)).map(|p| text(&p));
+        assert_eq!(double_quote("\"hello\""), Ok("hello".to_string()));
+        assert_eq!(double_quote("\"hello\\$\""), Ok("hello$".to_string()));
+        assert_eq!(double_quote("\"hello\\`\""), Ok("hello`".to_string()));
+        assert_eq!(double_quote("\"hello\\\"\""), Ok("hello\"".to_string()));
+        assert_eq!(double_quote("\"hello\\\\\""), Ok("hello\\".to_string()));
+        assert_eq!(double_quote("\"hello\\\n\""), Ok("hello".to_string()));
+        assert_eq!(double_quote("\"\""), Ok(String::new()));
+        assert!(double_quote("\"hello").is_err());
+        assert!(double_quote("\"hello\\").is_err());
     }
 
     #[test]
     fn test_no_quote() {
-        assert_eq!(
-            no_quote.parse_next(&mut Stream::new("hello ")),
-            Ok("hello".to_string())
-        );
-        assert_eq!(
-            no_quote.parse_next(&mut Stream::new("hello\t")),
-            Ok("hello".to_string())
-        );
-        assert_eq!(
-            no_quote.parse_next(&mut Stream::new("hello\r")),
-            Ok("hello".to_string())
-        );
-        assert_eq!(
-            no_quote.parse_next(&mut Stream::new("hello\n")),
-            Ok("hello".to_string())
-        );
-        assert_eq!(
-            no_quote.parse_next(&mut Stream::new("hello world")),
-            Ok("hello".to_string())
-        );
-        assert_eq!(
-            no_quote.parse_next(&mut Stream::new("hello\\ world\n")),
-            Ok("hello world".to_string())
-        );
-        assert!(no_quote
-            .parse_next(&mut Stream::new("hello"))
-            .is_err(),);
+        let no_quote = |input: &str| no_quote(&mut Stream::new(input)).map(|p| text(&p));
+        assert_eq!(no_quote("hello "), Ok("hello".to_string()));
+        assert_eq!(no_quote("hello\t"), Ok("hello".to_string()));
+        assert_eq!(no_quote("hello\r"), Ok("hello".to_string()));
+        assert_eq!(no_quote("hello\n"), Ok("hello".to_string()));
+        assert_eq!(no_quote("hello world"), Ok("hello".to_string()));
+        assert_eq!(no_quote("hello\\ world\n"), Ok("hello world".to_string()));
+        assert_eq!(no_quote("hello\\\nworld\n"), Ok("helloworld".to_string()));
+        assert_eq!(no_quote("hello|world"), Ok("hello".to_string()));
+        assert_eq!(no_quote("hello>world"), Ok("hello".to_string()));
+        assert!(no_quote("hello").is_err());
     }
 
     #[test]
     fn test_command_arg() {
+        assert_eq!(word("hello\n"), "hello");
+        assert_eq!(word("hello world\n"), "hello");
+        assert_eq!(word("'hello world'\n"), "hello world");
+        assert_eq!(word("'hello' world\n"), "hello");
+        assert_eq!(word("hello'world'\n"), "helloworld");
+        assert_eq!(word("\"hello world\"\n"), "hello world");
+        assert_eq!(word("\"hello\" world\"\n"), "hello");
+        assert_eq!(word("\"hello\\\" world\"\n"), "hello\" world");
+        assert_eq!(word("\"hello\\$ world\"\n"), "hello$ world");
+        assert_eq!(word("\"hello\\` world\"\n"), "hello` world");
+        assert_eq!(word("\"hello\\\n world\"\n"), "hello world");
+        assert_eq!(word("\"hello\\x world\"\n"), "hello\\x world");
+        assert_eq!(word("\"hello\\$\"\n"), "hello$");
+        assert_eq!(word("hello\\ world\n"), "hello world");
+        assert_eq!(word("'hello\\\\world'\n"), "hello\\\\world");
+        assert!(word_token(&mut Stream::new(" ")).is_err())
+    }
+
+    #[test]
+    fn test_word_parts() {
         assert_eq!(
-            command_token(&mut Stream::new("hello\n")).unwrap(),
-            CommandToken("hello".into())
-        );
-        assert_eq!(
-            command_token(&mut Stream::new("hello world\n")).unwrap(),
-            CommandToken("hello".into())
-        );
-        assert_eq!(
-            command_token(&mut Stream::new("'hello world'\n")).unwrap(),
-            CommandToken("hello world".into())
-        );
-        assert_eq!(
-            command_token(&mut Stream::new("'hello' world\n")).unwrap(),
-            CommandToken("hello".into())
-        );
-        assert_eq!(
-            command_token(&mut Stream::new("hello'world'\n")).unwrap(),
-            CommandToken("helloworld".into())
-        );
-        assert_eq!(
-            command_token(&mut Stream::new("\"hello world\"\n")).unwrap(),
-            CommandToken("hello world".into())
-        );
-        assert_eq!(
-            command_token(&mut Stream::new("\"hello\" world\"\n")).unwrap(),
-            CommandToken("hello".into())
-        );
-        assert_eq!(
-            command_token(&mut Stream::new("\"hello\\\" world\"\n")).unwrap(),
-            CommandToken("hello\" world".into())
-        );
-        assert_eq!(
-            command_token(&mut Stream::new("\"hello\\$ world\"\n")).unwrap(),
-            CommandToken("hello$ world".into())
+            word_token(&mut Stream::new("a\\ b'c'\"d\"\n")).unwrap(),
+            Word(vec![
+                WordPart::Literal("a".into()),
+                WordPart::Escaped(' '),
+                WordPart::Literal("b".into()),
+                WordPart::SingleQuoted("c".into()),
+                WordPart::DoubleQuoted(vec![WordPart::Literal("d".into())]),
+            ])
         );
+    }
+
+    #[test]
+    fn test_parameter() {
         assert_eq!(
-            command_token(&mut Stream::new("\"hello\\` world\"\n")).unwrap(),
-            CommandToken("hello` world".into())
+            word_token(&mut Stream::new("$HOME/bin\n")).unwrap(),
+            Word(vec![
+                WordPart::Parameter("HOME".into()),
+                WordPart::Literal("/bin".into()),
+            ])
         );
         assert_eq!(
-            command_token(&mut Stream::new("\"hello\\\n world\"\n")).unwrap(),
-            CommandToken("hello\n world".into())
+            word_token(&mut Stream::new("${a}b$1$?\n")).unwrap(),
+            Word(vec![
+                WordPart::Parameter("a".into()),
+                WordPart::Literal("b".into()),
+                WordPart::Parameter("1".into()),
+                WordPart::Parameter("?".into()),
+            ])
         );
         assert_eq!(
-            command_token(&mut Stream::new("\"hello\\x world\"\n")).unwrap(),
-            CommandToken("hello\\x world".into())
+            word_token(&mut Stream::new("${10}\n")).unwrap(),
+            Word(vec![WordPart::Parameter("10".into())])
         );
         assert_eq!(
-            command_token(&mut Stream::new("\"hello\\$\"\n")).unwrap(),
-            CommandToken("hello$".into())
+            word_token(&mut Stream::new("${a[1]}${a[@]}$a[1]\n")).unwrap(),
+            Word(vec![
+                WordPart::Parameter("a[1]".into()),
+                WordPart::Parameter("a[@]".into()),
+                WordPart::Parameter("a".into()),
+                WordPart::Literal("[1]".into()),
+            ])
         );
         assert_eq!(
-            command_token(&mut Stream::new("hello\\ world\n")).unwrap(),
-            CommandToken("hello world".into())
+            word_token(&mut Stream::new("\"$a b\"\n")).unwrap(),
+            Word(vec![WordPart::DoubleQuoted(vec![
+                WordPart::Parameter("a".into()),
+                WordPart::Literal(" b".into()),
+            ])])
         );
         assert_eq!(
-            command_token(&mut Stream::new("'hello\\\\world'\n")).unwrap(),
-            CommandToken("hello\\\\world".into())
+            word_token(&mut Stream::new("a$ $\n")).unwrap(),
+            Word(vec![WordPart::Literal("a$".into())])
         );
-        assert!(command_token(&mut Stream::new(" ")).is_err())
+        assert!(word_token(&mut Stream::new("${a\n")).is_err());
     }
 }
@@ -0,0 +1,1138 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::ast::{
+    AndOr, Assignment, CaseItem, Command, CompoundCommand, ConditionalExpr, Connector, List,
+    ListItem, Pipeline, Redirect, SimpleCommand, TimeFormat, Word, WordPart,
+};
+
+use super::{lex, ParseError, Token};
+
+/// Words closing a compound command, they cannot start a new command
+const CLOSING_WORDS: [&str; 9] = [
+    "then", "elif", "else", "fi", "do", "done", "esac", "}", "]]",
+];
+
+/// Words starting a compound command
+const OPENING_WORDS: [&str; 7] = ["{", "if", "while", "until", "for", "case", "[["];
+
+/// Binary operators of `[[ ]]`, besides `<` and `>` which are lexed as redirects
+const CONDITIONAL_OPERATORS: [&str; 13] = [
+    "=", "==", "!=", "=~", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// Token with the comments attached to it
+struct Lexeme {
+    raw: String,
+    token: Token,
+    /// Comment lines before the token, or the comment ending the line for a newline
+    comments: Vec<String>,
+    /// Aliases whose expansion produced this token, they are not expanded again
+    aliases: Vec<String>,
+    /// The word is in a position where aliases are expanded
+    check_alias: bool,
+}
+
+impl Lexeme {
+    fn new(raw: String, token: Token, comments: Vec<String>) -> Lexeme {
+        Lexeme {
+            raw,
+            token,
+            comments,
+            aliases: vec![],
+            check_alias: false,
+        }
+    }
+}
+
+/// Recursive descent parser turning the lexed tokens into a [`List`]
+pub(super) struct Parser<'a> {
+    tokens: VecDeque<Lexeme>,
+    aliases: &'a BTreeMap<String, String>,
+    /// Comments of consumed tokens, not yet attached to the tree
+    pending: Vec<String>,
+    /// Comment lines after the last token
+    trailing: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(parsed: Vec<(String, Token)>, aliases: &'a BTreeMap<String, String>) -> Parser<'a> {
+        let mut tokens = VecDeque::new();
+        let mut comments = vec![];
+        let mut inline = None;
+        let mut line_start = true;
+        for (raw, token) in parsed {
+            match token {
+                Token::Comment(comment) if line_start => comments.push(comment),
+                Token::Comment(comment) => inline = Some(comment),
+                Token::Newline => {
+                    line_start = true;
+                    let comments = inline.take().into_iter().collect();
+                    tokens.push_back(Lexeme::new(raw, token, comments));
+                }
+                token => {
+                    line_start = false;
+                    tokens.push_back(Lexeme::new(raw, token, std::mem::take(&mut comments)));
+                }
+            }
+        }
+        Parser {
+            tokens,
+            aliases,
+            pending: vec![],
+            trailing: comments,
+        }
+    }
+
+    pub fn program(mut self) -> Result<List, ParseError> {
+        let list = self.list()?;
+        match self.tokens.is_empty() {
+            true => Ok(list),
+            false => Err(self.unexpected()),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.front().map(|lexeme| &lexeme.token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.tokens.pop_front().map(|lexeme| {
+            self.pending.extend(lexeme.comments);
+            lexeme.token
+        })
+    }
+
+    /// Comments seen since the last call, including those before the next token
+    fn take_comments(&mut self) -> Vec<String> {
+        let mut comments = std::mem::take(&mut self.pending);
+        match self.tokens.front_mut() {
+            Some(lexeme) if lexeme.token != Token::Newline => comments.append(&mut lexeme.comments),
+            Some(_) => {}
+            None => comments.append(&mut self.trailing),
+        }
+        comments
+    }
+
+    /// Comment ending the current line, if the next token is a newline
+    fn take_inline_comment(&mut self) -> Option<String> {
+        match self.tokens.front_mut() {
+            Some(lexeme) if lexeme.token == Token::Newline => lexeme.comments.pop(),
+            _ => None,
+        }
+    }
+
+    /// Error for the next token, or [`ParseError::Incomplete`] if there is none
+    fn unexpected(&self) -> ParseError {
+        match self.tokens.front() {
+            None => ParseError::Incomplete,
+            Some(Lexeme {
+                token: Token::Newline,
+                ..
+            }) => ParseError::Unexpected("newline".into()),
+            Some(lexeme) => ParseError::Unexpected(lexeme.raw.trim().to_string()),
+        }
+    }
+
+    /// Unquoted text of the next token if it is a word
+    fn peek_literal(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Word(word)) => word.as_literal(),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(token) if *token == expected => {
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn expect_reserved(&mut self, reserved: &str) -> Result<(), ParseError> {
+        match self.peek_literal() {
+            Some(word) if word == reserved => {
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn next_word(&mut self) -> Result<Word, ParseError> {
+        match self.peek() {
+            Some(Token::Word(_)) => match self.next() {
+                Some(Token::Word(word)) => Ok(word),
+                _ => unreachable!(),
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Make the next word a candidate for alias expansion
+    fn check_alias(&mut self) {
+        if let Some(lexeme) = self.tokens.front_mut() {
+            lexeme.check_alias = true;
+        }
+    }
+
+    /// Alias definition for the next word, if it is a candidate for expansion
+    fn peek_alias(&self) -> Option<(&'a String, &'a String)> {
+        match self.tokens.front()? {
+            Lexeme {
+                token: Token::Word(word),
+                aliases: expanding,
+                check_alias: true,
+                ..
+            } => {
+                let name = word.as_literal()?;
+                match expanding.iter().any(|a| a == name) {
+                    true => None,
+                    false => self.aliases.get_key_value(name),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace the next word by its alias definition, as long as it is a candidate
+    fn expand_aliases(&mut self) -> Result<(), ParseError> {
+        while let Some((name, value)) = self.peek_alias() {
+            let tokens = lex(value).map_err(|_| ParseError::Unexpected(name.clone()))?;
+
+            let lexeme = self.tokens.pop_front().expect("alias name was peeked");
+            // a definition ending with a blank makes the following word a candidate too
+            if value.ends_with([' ', '\t']) {
+                self.check_alias();
+            }
+            let mut expanding = lexeme.aliases;
+            expanding.push(name.clone());
+            for (raw, token) in tokens.into_iter().rev() {
+                self.tokens.push_front(Lexeme {
+                    aliases: expanding.clone(),
+                    ..Lexeme::new(raw, token, vec![])
+                });
+            }
+
+            // the expansion takes the place of the alias name
+            if let Some(front) = self.tokens.front_mut() {
+                let mut comments = lexeme.comments;
+                comments.append(&mut front.comments);
+                front.comments = comments;
+            }
+            self.check_alias();
+        }
+        Ok(())
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.next();
+        }
+    }
+
+    fn at_command_start(&self) -> bool {
+        match self.peek() {
+            Some(Token::Word(_)) => !self
+                .peek_literal()
+                .is_some_and(|word| CLOSING_WORDS.contains(&word)),
+            Some(Token::Redirect(_)) | Some(Token::LeftParen) => true,
+            _ => false,
+        }
+    }
+
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut items = vec![];
+        loop {
+            self.skip_newlines();
+            if !self.at_command_start() {
+                break;
+            }
+
+            let comments = self.take_comments();
+            let and_or = self.and_or()?;
+            let (background, last) = match self.peek() {
+                Some(Token::Background) => {
+                    self.next();
+                    (true, false)
+                }
+                Some(Token::Semicolon) => {
+                    self.next();
+                    (false, false)
+                }
+                Some(Token::Newline) => (false, false),
+                _ => (false, true),
+            };
+            items.push(ListItem {
+                comments,
+                comment: self.take_inline_comment(),
+                ..ListItem::new(and_or, background)
+            });
+            if last {
+                break;
+            }
+        }
+        Ok(List {
+            items,
+            comments: self.take_comments(),
+        })
+    }
+
+    /// Non empty list inside a compound command
+    fn compound_list(&mut self) -> Result<List, ParseError> {
+        let list = self.list()?;
+        match list.items.is_empty() {
+            true => Err(self.unexpected()),
+            false => Ok(list),
+        }
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = vec![];
+        loop {
+            let connector = match self.peek() {
+                Some(Token::And) => Connector::And,
+                Some(Token::Or) => Connector::Or,
+                _ => break,
+            };
+            self.next();
+            self.skip_newlines();
+            rest.push((connector, self.pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut pipeline = Pipeline::default();
+        loop {
+            match self.peek_literal() {
+                Some("!") => {
+                    self.next();
+                    pipeline.negated = !pipeline.negated;
+                }
+                Some("time") if pipeline.time.is_none() => {
+                    self.next();
+                    pipeline.time = Some(TimeFormat::Default);
+                    if self.peek_literal() == Some("-p") {
+                        self.next();
+                        pipeline.time = Some(TimeFormat::Posix);
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        pipeline.commands.push(self.command()?);
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            self.skip_newlines();
+            pipeline.commands.push(self.command()?);
+        }
+        Ok(pipeline)
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        let reserved = self
+            .peek_literal()
+            .is_some_and(|word| OPENING_WORDS.contains(&word) || CLOSING_WORDS.contains(&word));
+        if !reserved {
+            self.check_alias();
+            self.expand_aliases()?;
+        }
+
+        let body = if self.peek() == Some(&Token::LeftParen) {
+            self.next();
+            let list = self.compound_list()?;
+            self.expect(Token::RightParen)?;
+            CompoundCommand::Subshell(list)
+        } else {
+            match self.peek_literal() {
+                Some("{") => self.brace_group()?,
+                Some("if") => self.if_clause()?,
+                Some("while") | Some("until") => self.loop_clause()?,
+                Some("for") => self.for_clause()?,
+                Some("case") => self.case_clause()?,
+                Some("[[") => self.conditional()?,
+                _ => return self.simple_command().map(Command::Simple),
+            }
+        };
+
+        let mut redirects = vec![];
+        while let Some(Token::Redirect(_)) = self.peek() {
+            if let Some(Token::Redirect(redirect)) = self.next() {
+                redirects.push(redirect);
+            }
+        }
+        Ok(Command::Compound { body, redirects })
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+        loop {
+            self.expand_aliases()?;
+            if !matches!(self.peek(), Some(Token::Word(_)) | Some(Token::Redirect(_))) {
+                break;
+            }
+            match self.next() {
+                // assignments are only recognised before the command name
+                Some(Token::Word(word)) if command.words.is_empty() => match assignment(word) {
+                    Ok(assignment) => {
+                        command.assignments.push(assignment);
+                        self.check_alias();
+                    }
+                    Err(word) => command.words.push(word),
+                },
+                Some(Token::Word(word)) => command.words.push(word),
+                Some(Token::Redirect(redirect)) => command.redirects.push(redirect),
+                _ => unreachable!(),
+            }
+        }
+
+        if command.assignments.is_empty()
+            && command.words.is_empty()
+            && command.redirects.is_empty()
+        {
+            return Err(self.unexpected());
+        }
+        Ok(command)
+    }
+
+    fn brace_group(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_reserved("{")?;
+        let list = self.compound_list()?;
+        self.expect_reserved("}")?;
+        Ok(CompoundCommand::BraceGroup(list))
+    }
+
+    fn if_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_reserved("if")?;
+        let mut branches = vec![];
+        loop {
+            let condition = self.compound_list()?;
+            self.expect_reserved("then")?;
+            let body = self.compound_list()?;
+            branches.push((condition, body));
+
+            match self.peek_literal() {
+                Some("elif") => {
+                    self.next();
+                }
+                _ => break,
+            }
+        }
+
+        let otherwise = match self.peek_literal() {
+            Some("else") => {
+                self.next();
+                Some(self.compound_list()?)
+            }
+            _ => None,
+        };
+        self.expect_reserved("fi")?;
+
+        Ok(CompoundCommand::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn loop_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        let is_while = self.peek_literal() == Some("while");
+        self.next();
+        let condition = self.compound_list()?;
+        let body = self.do_group()?;
+
+        match is_while {
+            true => Ok(CompoundCommand::While { condition, body }),
+            false => Ok(CompoundCommand::Until { condition, body }),
+        }
+    }
+
+    fn for_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_reserved("for")?;
+        let name = match self.peek_literal() {
+            Some(name) if is_name(name) => name.to_string(),
+            _ => return Err(self.unexpected()),
+        };
+        self.next();
+
+        self.skip_newlines();
+        let words = match self.peek_literal() {
+            Some("in") => {
+                self.next();
+                let mut words = vec![];
+                while let Some(Token::Word(_)) = self.peek() {
+                    words.push(self.next_word()?);
+                }
+                match self.peek() {
+                    Some(Token::Semicolon) | Some(Token::Newline) => {
+                        self.next();
+                    }
+                    _ => return Err(self.unexpected()),
+                }
+                Some(words)
+            }
+            _ => {
+                if self.peek() == Some(&Token::Semicolon) {
+                    self.next();
+                }
+                None
+            }
+        };
+        self.skip_newlines();
+        let body = self.do_group()?;
+
+        Ok(CompoundCommand::For { name, words, body })
+    }
+
+    fn do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_reserved("do")?;
+        let body = self.compound_list()?;
+        self.expect_reserved("done")?;
+        Ok(body)
+    }
+
+    fn case_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_reserved("case")?;
+        let word = self.next_word()?;
+        self.skip_newlines();
+        self.expect_reserved("in")?;
+        self.skip_newlines();
+
+        let mut items = vec![];
+        while self.peek_literal() != Some("esac") {
+            if self.peek() == Some(&Token::LeftParen) {
+                self.next();
+            }
+            let mut patterns = vec![self.next_word()?];
+            while self.peek() == Some(&Token::Pipe) {
+                self.next();
+                patterns.push(self.next_word()?);
+            }
+            self.expect(Token::RightParen)?;
+
+            let body = self.list()?;
+            items.push(CaseItem { patterns, body });
+
+            match self.peek() {
+                Some(Token::DoubleSemicolon) => {
+                    self.next();
+                    self.skip_newlines();
+                }
+                _ if self.peek_literal() == Some("esac") => {}
+                _ => return Err(self.unexpected()),
+            }
+        }
+        self.expect_reserved("esac")?;
+
+        Ok(CompoundCommand::Case { word, items })
+    }
+}
+
+/// Parser for the expression of `[[ ]]`, from the lowest precedence: `||`,
+/// `&&`, `!` then primaries
+impl Parser<'_> {
+    fn conditional(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.expect_reserved("[[")?;
+        let expr = self.conditional_or()?;
+        self.expect_reserved("]]")?;
+        Ok(CompoundCommand::Conditional(expr))
+    }
+
+    fn conditional_or(&mut self) -> Result<ConditionalExpr, ParseError> {
+        let mut expr = self.conditional_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            self.skip_newlines();
+            expr = ConditionalExpr::Or(Box::new(expr), Box::new(self.conditional_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn conditional_and(&mut self) -> Result<ConditionalExpr, ParseError> {
+        let mut expr = self.conditional_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            self.skip_newlines();
+            expr = ConditionalExpr::And(Box::new(expr), Box::new(self.conditional_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn conditional_not(&mut self) -> Result<ConditionalExpr, ParseError> {
+        match self.peek_literal() {
+            Some("!") => {
+                self.next();
+                Ok(ConditionalExpr::Not(Box::new(self.conditional_not()?)))
+            }
+            _ => self.conditional_primary(),
+        }
+    }
+
+    fn conditional_primary(&mut self) -> Result<ConditionalExpr, ParseError> {
+        self.skip_newlines();
+        if self.peek() == Some(&Token::LeftParen) {
+            self.next();
+            let expr = self.conditional_or()?;
+            self.expect(Token::RightParen)?;
+            return Ok(expr);
+        }
+        if self.peek_literal() == Some("]]") {
+            return Err(self.unexpected());
+        }
+
+        let lhs = self.next_word()?;
+        // `-op operand`, unless what follows is a binary operator or the end
+        if let Some(op) = lhs.as_literal().filter(|op| is_unary_operator(op)) {
+            let operand = match self.peek() {
+                Some(Token::Word(word)) => word
+                    .as_literal()
+                    .is_none_or(|w| w != "]]" && !CONDITIONAL_OPERATORS.contains(&w)),
+                _ => false,
+            };
+            if operand {
+                return Ok(ConditionalExpr::Unary {
+                    op: op.to_string(),
+                    operand: self.next_word()?,
+                });
+            }
+        }
+
+        let op = match self.peek() {
+            Some(Token::Word(word)) => match word.as_literal() {
+                Some(op) if CONDITIONAL_OPERATORS.contains(&op) => op.to_string(),
+                _ => return Ok(ConditionalExpr::Word(lhs)),
+            },
+            // `<` and `>` are lexed with their operand
+            Some(Token::Redirect(Redirect::Input { n: 0, word })) => {
+                let rhs = word.clone();
+                self.next();
+                let op = "<".to_string();
+                return Ok(ConditionalExpr::Binary { lhs, op, rhs });
+            }
+            Some(Token::Redirect(Redirect::Output { n: 1, word })) => {
+                let rhs = word.clone();
+                self.next();
+                let op = ">".to_string();
+                return Ok(ConditionalExpr::Binary { lhs, op, rhs });
+            }
+            _ => return Ok(ConditionalExpr::Word(lhs)),
+        };
+        self.next();
+
+        let rhs = match op.as_str() {
+            "=~" => self.regex_word()?,
+            _ => self.next_word()?,
+        };
+        Ok(ConditionalExpr::Binary { lhs, op, rhs })
+    }
+
+    /// Right side of `=~`, where parentheses and `|` are part of the word
+    /// until the next blank
+    fn regex_word(&mut self) -> Result<Word, ParseError> {
+        let mut parts: Vec<WordPart> = vec![];
+        while let Some(lexeme) = self.tokens.front() {
+            if !parts.is_empty() && lexeme.raw.starts_with([' ', '\t', '\\']) {
+                break;
+            }
+            let new_parts = match &lexeme.token {
+                Token::Word(word) if word.as_literal() == Some("]]") => break,
+                Token::Word(word) => word.0.clone(),
+                Token::LeftParen | Token::RightParen | Token::Pipe => {
+                    vec![WordPart::Literal(lexeme.raw.trim_start().to_string())]
+                }
+                _ => break,
+            };
+            for part in new_parts {
+                match (parts.last_mut(), part) {
+                    (Some(WordPart::Literal(last)), WordPart::Literal(s)) => last.push_str(&s),
+                    (_, part) => parts.push(part),
+                }
+            }
+            self.next();
+        }
+
+        match parts.is_empty() {
+            true => Err(self.unexpected()),
+            false => Ok(Word(parts)),
+        }
+    }
+}
+
+/// `-` followed by a letter, such as `-f`
+fn is_unary_operator(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next() == Some('-')
+        && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.next().is_none()
+}
+
+/// Valid variable name, `[A-Za-z_][A-Za-z0-9_]*`
+pub(crate) fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split a `name=value` word, or give the word back if it is not an assignment
+fn assignment(word: Word) -> Result<Assignment, Word> {
+    let (name, value) = match word.0.first() {
+        Some(WordPart::Literal(first)) => match first.split_once('=') {
+            Some((name, value)) if is_name(name) => (name.to_string(), value.to_string()),
+            _ => return Err(word),
+        },
+        _ => return Err(word),
+    };
+
+    let mut parts = word.0;
+    match value.is_empty() {
+        true => {
+            parts.remove(0);
+        }
+        false => parts[0] = WordPart::Literal(value),
+    }
+    Ok(Assignment {
+        name,
+        value: Word(parts),
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::parse::{parse, parse_with_aliases};
+
+    use super::*;
+
+    fn simple(words: &[&str]) -> Command {
+        Command::Simple(SimpleCommand {
+            words: words.iter().map(|&w| w.into()).collect(),
+            ..Default::default()
+        })
+    }
+
+    fn pipeline(commands: Vec<Command>) -> AndOr {
+        AndOr {
+            first: Pipeline {
+                commands,
+                ..Default::default()
+            },
+            rest: vec![],
+        }
+    }
+
+    fn list(items: Vec<AndOr>) -> List {
+        List {
+            items: items
+                .into_iter()
+                .map(|and_or| ListItem::new(and_or, false))
+                .collect(),
+            comments: vec![],
+        }
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(parse(""), Ok(List::default()));
+        assert_eq!(
+            parse("\n\n  # comment\n"),
+            Ok(List {
+                items: vec![],
+                comments: vec![" comment".into()],
+            })
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            parse("# first\na # inline\n{\n  b\n  # last\n}"),
+            Ok(List {
+                items: vec![
+                    ListItem {
+                        comments: vec![" first".into()],
+                        comment: Some(" inline".into()),
+                        ..ListItem::new(pipeline(vec![simple(&["a"])]), false)
+                    },
+                    ListItem::new(
+                        pipeline(vec![Command::Compound {
+                            body: CompoundCommand::BraceGroup(List {
+                                comments: vec![" last".into()],
+                                ..list(vec![pipeline(vec![simple(&["b"])])])
+                            }),
+                            redirects: vec![],
+                        }]),
+                        false
+                    ),
+                ],
+                comments: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn simple_command() {
+        assert_eq!(
+            parse("echo hello > file"),
+            Ok(list(vec![pipeline(vec![Command::Simple(SimpleCommand {
+                words: vec!["echo".into(), "hello".into()],
+                redirects: vec![Redirect::Output {
+                    n: 1,
+                    word: "file".into()
+                }],
+                ..Default::default()
+            })])]))
+        );
+        assert_eq!(
+            parse("echo \"$HOME\""),
+            Ok(list(vec![pipeline(vec![Command::Simple(SimpleCommand {
+                words: vec![
+                    "echo".into(),
+                    Word(vec![WordPart::DoubleQuoted(vec![WordPart::Parameter(
+                        "HOME".into()
+                    )])])
+                ],
+                ..Default::default()
+            })])]))
+        );
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(
+            parse("A=1 B=\"$x y\" C= cmd D=2"),
+            Ok(list(vec![pipeline(vec![Command::Simple(SimpleCommand {
+                assignments: vec![
+                    Assignment {
+                        name: "A".into(),
+                        value: "1".into(),
+                    },
+                    Assignment {
+                        name: "B".into(),
+                        value: Word(vec![WordPart::DoubleQuoted(vec![
+                            WordPart::Parameter("x".into()),
+                            WordPart::Literal(" y".into()),
+                        ])]),
+                    },
+                    Assignment {
+                        name: "C".into(),
+                        value: Word(vec![]),
+                    },
+                ],
+                words: vec!["cmd".into(), "D=2".into()],
+                ..Default::default()
+            })])]))
+        );
+        assert_eq!(
+            parse("'A=1' 1A=2"),
+            Ok(list(vec![pipeline(vec![Command::Simple(SimpleCommand {
+                words: vec![
+                    Word(vec![WordPart::SingleQuoted("A=1".into())]),
+                    "1A=2".into()
+                ],
+                ..Default::default()
+            })])]))
+        );
+    }
+
+    #[test]
+    fn pipelines_and_lists() {
+        assert_eq!(
+            parse("a | b && c || d; e &\nf"),
+            Ok(List {
+                items: vec![
+                    ListItem::new(
+                        AndOr {
+                            first: Pipeline {
+                                commands: vec![simple(&["a"]), simple(&["b"])],
+                                ..Default::default()
+                            },
+                            rest: vec![
+                                (
+                                    Connector::And,
+                                    Pipeline {
+                                        commands: vec![simple(&["c"])],
+                                        ..Default::default()
+                                    }
+                                ),
+                                (
+                                    Connector::Or,
+                                    Pipeline {
+                                        commands: vec![simple(&["d"])],
+                                        ..Default::default()
+                                    }
+                                ),
+                            ],
+                        },
+                        false,
+                    ),
+                    ListItem::new(pipeline(vec![simple(&["e"])]), true,),
+                    ListItem::new(pipeline(vec![simple(&["f"])]), false,),
+                ],
+                comments: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn negation_and_time() {
+        assert_eq!(
+            parse("! a | b && time -p ! c"),
+            Ok(list(vec![AndOr {
+                first: Pipeline {
+                    negated: true,
+                    commands: vec![simple(&["a"]), simple(&["b"])],
+                    ..Default::default()
+                },
+                rest: vec![(
+                    Connector::And,
+                    Pipeline {
+                        negated: true,
+                        time: Some(TimeFormat::Posix),
+                        commands: vec![simple(&["c"])],
+                    }
+                )],
+            }]))
+        );
+        assert_eq!(
+            parse("echo ! time"),
+            Ok(list(vec![pipeline(vec![simple(&["echo", "!", "time"])])]))
+        );
+        assert_eq!(parse("!"), Err(ParseError::Unexpected("newline".into())));
+    }
+
+    #[test]
+    fn conditional() {
+        let conditional = |expr| {
+            Ok(list(vec![pipeline(vec![Command::Compound {
+                body: CompoundCommand::Conditional(expr),
+                redirects: vec![],
+            }])]))
+        };
+        let x = || Word(vec![WordPart::Parameter("x".into())]);
+        assert_eq!(
+            parse("[[ -f a && ( $x == b* ||\n ! c ) ]]"),
+            conditional(ConditionalExpr::And(
+                Box::new(ConditionalExpr::Unary {
+                    op: "-f".into(),
+                    operand: "a".into()
+                }),
+                Box::new(ConditionalExpr::Or(
+                    Box::new(ConditionalExpr::Binary {
+                        lhs: x(),
+                        op: "==".into(),
+                        rhs: "b*".into()
+                    }),
+                    Box::new(ConditionalExpr::Not(Box::new(ConditionalExpr::Word(
+                        "c".into()
+                    ))))
+                ))
+            ))
+        );
+        assert_eq!(
+            parse("[[ $x < b ]]"),
+            conditional(ConditionalExpr::Binary {
+                lhs: x(),
+                op: "<".into(),
+                rhs: "b".into()
+            })
+        );
+        assert_eq!(
+            parse("[[ -n ]]"),
+            conditional(ConditionalExpr::Word("-n".into()))
+        );
+        assert_eq!(
+            parse("[[ $x =~ ^(a|b) ]]"),
+            conditional(ConditionalExpr::Binary {
+                lhs: x(),
+                op: "=~".into(),
+                rhs: "^(a|b)".into()
+            })
+        );
+        assert_eq!(parse("[[ a"), Err(ParseError::Unexpected("newline".into())));
+        assert_eq!(parse("[[ ]]"), Err(ParseError::Unexpected("]]".into())));
+    }
+
+    #[test]
+    fn compound_commands() {
+        assert_eq!(
+            parse("{ a; b; } > out"),
+            Ok(list(vec![pipeline(vec![Command::Compound {
+                body: CompoundCommand::BraceGroup(list(vec![
+                    pipeline(vec![simple(&["a"])]),
+                    pipeline(vec![simple(&["b"])]),
+                ])),
+                redirects: vec![Redirect::Output {
+                    n: 1,
+                    word: "out".into()
+                }],
+            }])]))
+        );
+        assert_eq!(
+            parse("(a)"),
+            Ok(list(vec![pipeline(vec![Command::Compound {
+                body: CompoundCommand::Subshell(list(vec![pipeline(vec![simple(&["a"])])])),
+                redirects: vec![],
+            }])]))
+        );
+        assert_eq!(
+            parse("if a; then b; elif c\nthen d; else e; fi"),
+            Ok(list(vec![pipeline(vec![Command::Compound {
+                body: CompoundCommand::If {
+                    branches: vec![
+                        (
+                            list(vec![pipeline(vec![simple(&["a"])])]),
+                            list(vec![pipeline(vec![simple(&["b"])])])
+                        ),
+                        (
+                            list(vec![pipeline(vec![simple(&["c"])])]),
+                            list(vec![pipeline(vec![simple(&["d"])])])
+                        ),
+                    ],
+                    otherwise: Some(list(vec![pipeline(vec![simple(&["e"])])])),
+                },
+                redirects: vec![],
+            }])]))
+        );
+        assert_eq!(
+            parse("while a; do b; done | c"),
+            Ok(list(vec![pipeline(vec![
+                Command::Compound {
+                    body: CompoundCommand::While {
+                        condition: list(vec![pipeline(vec![simple(&["a"])])]),
+                        body: list(vec![pipeline(vec![simple(&["b"])])]),
+                    },
+                    redirects: vec![],
+                },
+                simple(&["c"]),
+            ])]))
+        );
+        assert_eq!(
+            parse("for x in 1 2\ndo\n  echo $x\ndone"),
+            Ok(list(vec![pipeline(vec![Command::Compound {
+                body: CompoundCommand::For {
+                    name: "x".into(),
+                    words: Some(vec!["1".into(), "2".into()]),
+                    body: list(vec![pipeline(vec![Command::Simple(SimpleCommand {
+                        words: vec!["echo".into(), Word(vec![WordPart::Parameter("x".into())])],
+                        ..Default::default()
+                    })])]),
+                },
+                redirects: vec![],
+            }])]))
+        );
+        assert_eq!(
+            parse("case $x in\n  a|b) one;;\n  (*) two\nesac"),
+            Ok(list(vec![pipeline(vec![Command::Compound {
+                body: CompoundCommand::Case {
+                    word: Word(vec![WordPart::Parameter("x".into())]),
+                    items: vec![
+                        CaseItem {
+                            patterns: vec!["a".into(), "b".into()],
+                            body: list(vec![pipeline(vec![simple(&["one"])])]),
+                        },
+                        CaseItem {
+                            patterns: vec!["*".into()],
+                            body: list(vec![pipeline(vec![simple(&["two"])])]),
+                        },
+                    ],
+                },
+                redirects: vec![],
+            }])]))
+        );
+    }
+
+    #[test]
+    fn reserved_words_as_arguments() {
+        assert_eq!(
+            parse("echo if then fi }"),
+            Ok(list(vec![pipeline(vec![simple(&[
+                "echo", "if", "then", "fi", "}"
+            ])])]))
+        );
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases = BTreeMap::from([
+            ("ll".to_string(), "ls -l".to_string()),
+            ("ls".to_string(), "ls --color".to_string()),
+            ("sudo".to_string(), "sudo ".to_string()),
+            ("both".to_string(), "a; b".to_string()),
+            ("loop".to_string(), "loop".to_string()),
+        ]);
+        let expand = |input| parse_with_aliases(input, &aliases);
+
+        assert_eq!(
+            expand("ll ll"),
+            Ok(list(vec![pipeline(vec![simple(&[
+                "ls", "--color", "-l", "ll"
+            ])])]))
+        );
+        assert_eq!(
+            expand("sudo ll | 'll'"),
+            Ok(list(vec![pipeline(vec![
+                simple(&["sudo", "ls", "--color", "-l"]),
+                Command::Simple(SimpleCommand {
+                    words: vec![Word(vec![WordPart::SingleQuoted("ll".into())])],
+                    ..Default::default()
+                })
+            ])]))
+        );
+        assert_eq!(
+            expand("both && loop"),
+            Ok(list(vec![
+                pipeline(vec![simple(&["a"])]),
+                AndOr {
+                    first: Pipeline {
+                        commands: vec![simple(&["b"])],
+                        ..Default::default()
+                    },
+                    rest: vec![(
+                        Connector::And,
+                        Pipeline {
+                            commands: vec![simple(&["loop"])],
+                            ..Default::default()
+                        }
+                    )],
+                },
+            ]))
+        );
+        assert_eq!(
+            expand("echo ll; if ll; then :; fi"),
+            parse("echo ll; if ls --color -l; then :; fi")
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(parse("if a; then b"), Err(ParseError::Incomplete));
+        assert_eq!(parse("while a"), Err(ParseError::Incomplete));
+        assert_eq!(parse("a &&"), Err(ParseError::Incomplete));
+        assert_eq!(parse("fi"), Err(ParseError::Unexpected("fi".into())));
+        assert_eq!(parse("a | ;"), Err(ParseError::Unexpected(";".into())));
+        assert_eq!(parse("if then"), Err(ParseError::Unexpected("then".into())));
+        assert_eq!(parse("a )"), Err(ParseError::Unexpected(")".into())));
+        assert_eq!(parse("for 1"), Err(ParseError::Unexpected("1".into())));
+    }
+
+    #[test]
+    fn line_continuation() {
+        assert_eq!(parse("echo ab\\\ncd"), parse("echo abcd"));
+        assert_eq!(parse("echo \"x\\\ny\" \\\n  z"), parse("echo \"xy\" z"));
+        // but not in single quotes
+        assert_ne!(parse("echo 'x\\\ny'"), parse("echo 'xy'"));
+    }
+
+    #[test]
+    fn large_numbers() {
+        // too large for a file descriptor, the digits are a word
+        assert_eq!(parse("echo 99999999999>f"), parse("echo 99999999999 >f"));
+        assert!(parse("shift 99999999999999999999").is_ok());
+    }
+}
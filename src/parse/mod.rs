@@ -1,43 +1,89 @@
-use std::io;
-use std::str::FromStr;
-
-use anyhow::{bail, Context, Result};
-use command::command_token;
+use command::word_token;
 use redirect::redirect_token;
+use std::collections::BTreeMap;
+
+use thiserror::Error;
 use winnow::{
-    ascii::multispace0,
-    combinator::{alt, preceded},
+    combinator::{alt, preceded, repeat},
+    error::ErrMode,
     stream::{Offset, Stream as _},
+    token::{take_till, take_while},
     ModalResult, Parser, Partial,
 };
 
-use crate::{
-    command::{BuiltinCommand, CommandArgs, InvalidCommand, PathCommand, StdioCommand},
-    utils::path_lookup_exact,
-};
-use crate::{
-    command::{Command, PipeCommands},
-    io::{PErr, PIn, POut, PType},
-};
+use crate::ast::{List, Redirect, Word};
 
 mod command;
+mod grammar;
 mod redirect;
 
-#[derive(Debug, PartialEq, Eq)]
+pub(crate) use grammar::is_name;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum Token {
+    Word(Word),
+    Redirect(Redirect),
+    /// `|`
     Pipe,
-    Redirect(RedirectToken),
-    Command(CommandToken),
+    /// `||`
+    Or,
+    /// `&&`
+    And,
+    /// `&`
+    Background,
+    /// `;`
+    Semicolon,
+    /// `;;`
+    DoubleSemicolon,
+    /// `(`
+    LeftParen,
+    /// `)`
+    RightParen,
+    Newline,
+    Comment(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    /// The input ends in the middle of a command, more lines are needed
+    #[error("syntax error: unexpected end of file")]
+    Incomplete,
+    #[error("syntax error near unexpected token `{0}'")]
+    Unexpected(String),
+}
+
+/// Parse a complete shell input into its syntax tree, without executing anything.
+pub fn parse(input: &str) -> Result<List, ParseError> {
+    parse_with_aliases(input, &BTreeMap::new())
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct CommandToken(String);
+/// Parse a complete shell input, expanding the given aliases
+pub(crate) fn parse_with_aliases(
+    input: &str,
+    aliases: &BTreeMap<String, String>,
+) -> Result<List, ParseError> {
+    let tokens = StreamCommandParser::new(input).finish()?;
+    grammar::Parser::new(tokens, aliases).program()
+}
 
-#[derive(Debug, PartialEq, Eq)]
-enum RedirectToken {
-    Input { n: i32, word: String },
-    Output { n: i32, word: String },
-    AppendOutput { n: i32, word: String },
+/// Parse input read a line at a time, which is [`ParseError::Incomplete`]
+/// while its last line is continued by a backslash
+pub(crate) fn parse_lines(
+    input: &str,
+    aliases: &BTreeMap<String, String>,
+) -> Result<List, ParseError> {
+    if StreamCommandParser::new(input).is_continued() {
+        return Err(ParseError::Incomplete);
+    }
+    parse_with_aliases(input, aliases)
+}
+
+/// Tokens of an alias definition, without comments
+fn lex(input: &str) -> Result<Vec<(String, Token)>, ParseError> {
+    let mut tokens = StreamCommandParser::new(input).finish()?;
+    tokens.pop();
+    tokens.retain(|(_, token)| !matches!(token, Token::Comment(_)));
+    Ok(tokens)
 }
 
 pub(crate) type Stream<'i> = Partial<&'i str>;
@@ -46,6 +92,7 @@ pub(crate) type Stream<'i> = Partial<&'i str>;
 pub(crate) struct StreamCommandParser {
     remaining: String,
     parsed: Vec<(String, Token)>,
+    incomplete: bool,
 }
 
 impl StreamCommandParser {
@@ -53,6 +100,7 @@ impl StreamCommandParser {
         let mut p = StreamCommandParser {
             remaining: line.to_string(),
             parsed: vec![],
+            incomplete: false,
         };
         p.parse();
         p
@@ -67,46 +115,31 @@ impl StreamCommandParser {
         self.parsed.is_empty() && self.remaining.trim().is_empty()
     }
 
-    pub fn finish(mut self) -> Result<PipeCommands> {
+    /// Tokens of the whole input, ending with a newline
+    fn finish(mut self) -> Result<Vec<(String, Token)>, ParseError> {
         self.push("\n");
 
-        let raw_input = self.input();
-
-        // invalid
+        // the lexer could not make sense of the rest of the input
         if !self.remaining.trim().is_empty() {
-            bail!("invalid command {}", &raw_input)
-        }
-
-        let mut tokens = vec![];
-        let mut commands = vec![];
-
-        let mut parsed = self.parsed;
-        parsed.push(("".into(), Token::Pipe));
-        for (_, arg) in parsed {
-            match arg {
-                Token::Pipe => {
-                    let command = tokens_to_stdio_command(std::mem::take(&mut tokens))
-                        .with_context(|| format!("invalid command: {}", &raw_input))?;
-                    commands.push(command);
-                }
-                token => tokens.push(token),
+            if self.incomplete {
+                return Err(ParseError::Incomplete);
             }
+            let near = self.remaining.split_whitespace().next().unwrap_or_default();
+            return Err(ParseError::Unexpected(near.to_string()));
         }
 
-        Ok(PipeCommands { commands })
+        Ok(self.parsed)
     }
 
-    pub fn remaining(&self) -> &str {
-        &self.remaining
+    /// Whether the input ends with a backslash escaping the end of the line
+    fn is_continued(&self) -> bool {
+        let rest = self.remaining.strip_suffix('\n').unwrap_or(&self.remaining);
+        let backslashes = rest.len() - rest.trim_end_matches('\\').len();
+        self.incomplete && backslashes % 2 == 1 && !rest.trim_start().starts_with('#')
     }
 
-    fn input(&self) -> String {
-        let mut input = String::new();
-        for (p, _) in &self.parsed {
-            input += p;
-        }
-        input += self.remaining();
-        input
+    pub fn remaining(&self) -> &str {
+        &self.remaining
     }
 
     fn parse(&mut self) {
@@ -121,71 +154,39 @@ impl StreamCommandParser {
                     std::mem::swap(&mut self.remaining, &mut parsed_input);
                     self.parsed.push((parsed_input, tok));
                 }
-                Err(_) => break,
+                Err(err) => {
+                    self.incomplete = matches!(err, ErrMode::Incomplete(_));
+                    break;
+                }
             }
         }
     }
 }
 
 fn token(stream: &mut Stream) -> ModalResult<Token> {
-    alt((
-        preceded(multispace0, "|").map(|_| Token::Pipe),
-        redirect_token.map(Token::Redirect),
-        command_token.map(Token::Command),
-    ))
+    preceded(
+        blank,
+        alt((
+            "\n".map(|_| Token::Newline),
+            "||".map(|_| Token::Or),
+            "|".map(|_| Token::Pipe),
+            "&&".map(|_| Token::And),
+            "&".map(|_| Token::Background),
+            ";;".map(|_| Token::DoubleSemicolon),
+            ";".map(|_| Token::Semicolon),
+            "(".map(|_| Token::LeftParen),
+            ")".map(|_| Token::RightParen),
+            preceded('#', take_till(0.., '\n')).map(|s: &str| Token::Comment(s.to_string())),
+            redirect_token.map(Token::Redirect),
+            word_token.map(Token::Word),
+        )),
+    )
     .parse_next(stream)
 }
 
-fn tokens_to_stdio_command(tokens: Vec<Token>) -> Result<StdioCommand> {
-    let mut redirect_args = vec![];
-    let mut command_args = vec![];
-    for tok in tokens {
-        match tok {
-            Token::Pipe => bail!("tokens should not contain pipe"),
-            Token::Redirect(redirect_arg) => redirect_args.push(redirect_arg),
-            Token::Command(command_arg) => command_args.push(command_arg),
-        }
-    }
-
-    if command_args.is_empty() {
-        bail!("no command args")
-    }
-
-    let redirect_pipes = redirect_args
-        .into_iter()
-        .map(|r| r.into_pipe())
-        .collect::<Result<Vec<_>>>()?;
-
-    let mut stdin = vec![];
-    let mut stdout = vec![];
-    let mut stderr = vec![];
-    for ptype in redirect_pipes {
-        match ptype {
-            PType::In(pin) => stdin.push(pin),
-            PType::Out(pout) => stdout.push(pout),
-            PType::Err(perr) => stderr.push(perr),
-        }
-    }
-    let stdin = stdin.pop().unwrap_or(PIn::Empty);
-    if stdout.is_empty() {
-        stdout.push(POut::Std(io::stdout()))
-    }
-    if stderr.is_empty() {
-        stderr.push(PErr::Std(io::stderr()))
-    }
-
-    let cmd = command_args.remove(0);
-
-    let args = CommandArgs(command_args.into_iter().map(|v| v.0).collect());
-    let command = match BuiltinCommand::from_str(&cmd.0) {
-        Ok(builtin) => Command::Builtin(builtin.with_args(args)),
-        Err(_) => match path_lookup_exact(&cmd.0) {
-            Ok(path) => Command::Path(PathCommand { path, args }),
-            Err(_) => Command::Invalid(InvalidCommand(cmd.0)),
-        },
-    };
-
-    Ok(StdioCommand::new(stdin, stdout, stderr, command))
+/// Spaces, tabs and escaped newlines between tokens
+fn blank(stream: &mut Stream) -> ModalResult<()> {
+    repeat(0.., alt((take_while(1.., [' ', '\t', '\r']), "\\\n"))).parse_next(stream)
 }
 
 #[cfg(test)]
@@ -193,24 +194,25 @@ mod test {
 
     use super::*;
 
+    use crate::ast::WordPart;
+
     fn parser(command: &str) -> StreamCommandParser {
-        StreamCommandParser::new(&format!("{command}\n"))
+        let mut p = StreamCommandParser::new(&format!("{command}\n"));
+        assert_eq!(p.parsed.pop(), Some(("\n".into(), Token::Newline)));
+        p
     }
 
     #[test]
     fn test_tokens_only_command() {
         assert_eq!(
             parser("hello").parsed,
-            vec![("hello".into(), Token::Command(CommandToken("hello".into())))],
+            vec![("hello".into(), Token::Word("hello".into()))],
         );
         assert_eq!(
             parser("hello world").parsed,
             vec![
-                ("hello".into(), Token::Command(CommandToken("hello".into()))),
-                (
-                    " world".into(),
-                    Token::Command(CommandToken("world".into()))
-                )
+                ("hello".into(), Token::Word("hello".into())),
+                (" world".into(), Token::Word("world".into()))
             ],
         );
         assert_eq!(
@@ -218,12 +220,9 @@ mod test {
             vec![
                 (
                     "'hello'".into(),
-                    Token::Command(CommandToken("hello".into()))
+                    Token::Word(Word(vec![WordPart::SingleQuoted("hello".into())]))
                 ),
-                (
-                    " world".into(),
-                    Token::Command(CommandToken("world".into()))
-                )
+                (" world".into(), Token::Word("world".into()))
             ],
         );
         assert_eq!(
@@ -231,16 +230,10 @@ mod test {
             vec![
                 (
                     "'hello world'".into(),
-                    Token::Command(CommandToken("hello world".into()))
+                    Token::Word(Word(vec![WordPart::SingleQuoted("hello world".into())]))
                 ),
-                (
-                    " hello".into(),
-                    Token::Command(CommandToken("hello".into()))
-                ),
-                (
-                    " world".into(),
-                    Token::Command(CommandToken("world".into()))
-                )
+                (" hello".into(), Token::Word("hello".into())),
+                (" world".into(), Token::Word("world".into()))
             ],
         );
     }
@@ -251,7 +244,7 @@ mod test {
             parser("> file").parsed,
             vec![(
                 "> file".into(),
-                Token::Redirect(RedirectToken::Output {
+                Token::Redirect(Redirect::Output {
                     n: 1,
                     word: "file".into()
                 })
@@ -262,7 +255,7 @@ mod test {
             parser("2>|file").parsed,
             vec![(
                 "2>|file".into(),
-                Token::Redirect(RedirectToken::Output {
+                Token::Redirect(Redirect::Output {
                     n: 2,
                     word: "file".into()
                 })
@@ -275,10 +268,10 @@ mod test {
         assert_eq!(
             parser("echo > file").parsed,
             vec![
-                ("echo".into(), Token::Command(CommandToken("echo".into()))),
+                ("echo".into(), Token::Word("echo".into())),
                 (
                     " > file".into(),
-                    Token::Redirect(RedirectToken::Output {
+                    Token::Redirect(Redirect::Output {
                         n: 1,
                         word: "file".into()
                     })
@@ -288,14 +281,11 @@ mod test {
         assert_eq!(
             parser("echo hello 2>|file").parsed,
             vec![
-                ("echo".into(), Token::Command(CommandToken("echo".into()))),
-                (
-                    " hello".into(),
-                    Token::Command(CommandToken("hello".into()))
-                ),
+                ("echo".into(), Token::Word("echo".into())),
+                (" hello".into(), Token::Word("hello".into())),
                 (
                     " 2>|file".into(),
-                    Token::Redirect(RedirectToken::Output {
+                    Token::Redirect(Redirect::Output {
                         n: 2,
                         word: "file".into()
                     })
@@ -305,14 +295,11 @@ mod test {
         assert_eq!(
             parser("echo hello >> file").parsed,
             vec![
-                ("echo".into(), Token::Command(CommandToken("echo".into()))),
-                (
-                    " hello".into(),
-                    Token::Command(CommandToken("hello".into()))
-                ),
+                ("echo".into(), Token::Word("echo".into())),
+                (" hello".into(), Token::Word("hello".into())),
                 (
                     " >> file".into(),
-                    Token::Redirect(RedirectToken::AppendOutput {
+                    Token::Redirect(Redirect::AppendOutput {
                         n: 1,
                         word: "file".into()
                     })
@@ -322,14 +309,11 @@ mod test {
         assert_eq!(
             parser("echo hello 2>> file").parsed,
             vec![
-                ("echo".into(), Token::Command(CommandToken("echo".into()))),
-                (
-                    " hello".into(),
-                    Token::Command(CommandToken("hello".into()))
-                ),
+                ("echo".into(), Token::Word("echo".into())),
+                (" hello".into(), Token::Word("hello".into())),
                 (
                     " 2>> file".into(),
-                    Token::Redirect(RedirectToken::AppendOutput {
+                    Token::Redirect(Redirect::AppendOutput {
                         n: 2,
                         word: "file".into()
                     })
@@ -343,10 +327,63 @@ mod test {
         assert_eq!(
             parser("one | two").parsed,
             vec![
-                ("one".into(), Token::Command(CommandToken("one".into()))),
+                ("one".into(), Token::Word("one".into())),
                 (" |".into(), Token::Pipe),
-                (" two".into(), Token::Command(CommandToken("two".into()))),
+                (" two".into(), Token::Word("two".into())),
             ]
         )
     }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            parser("a&&b||c;d&").parsed,
+            vec![
+                ("a".into(), Token::Word("a".into())),
+                ("&&".into(), Token::And),
+                ("b".into(), Token::Word("b".into())),
+                ("||".into(), Token::Or),
+                ("c".into(), Token::Word("c".into())),
+                (";".into(), Token::Semicolon),
+                ("d".into(), Token::Word("d".into())),
+                ("&".into(), Token::Background),
+            ]
+        );
+        assert_eq!(
+            parser("(a) ;;").parsed,
+            vec![
+                ("(".into(), Token::LeftParen),
+                ("a".into(), Token::Word("a".into())),
+                (")".into(), Token::RightParen),
+                (" ;;".into(), Token::DoubleSemicolon),
+            ]
+        );
+    }
+
+    #[test]
+    fn comment() {
+        assert_eq!(
+            parser("echo a#b # comment").parsed,
+            vec![
+                ("echo".into(), Token::Word("echo".into())),
+                (" a#b".into(), Token::Word("a#b".into())),
+                (" # comment".into(), Token::Comment(" comment".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert_eq!(parse("echo 'hello"), Err(ParseError::Incomplete));
+        assert_eq!(parse("echo hello |"), Err(ParseError::Incomplete));
+        assert_eq!(parse("echo >"), Err(ParseError::Unexpected(">".into())));
+
+        // a backslash at the end of a line waits for the next one
+        let lines = |input: &str| parse_lines(input, &BTreeMap::new());
+        assert_eq!(lines("echo ab\\\n"), Err(ParseError::Incomplete));
+        assert_eq!(lines("echo ab\\"), Err(ParseError::Incomplete));
+        assert_eq!(lines("echo ab\\\\\n"), parse("echo ab\\\\"));
+        assert!(lines("echo ab # c\\\n").is_ok());
+        assert_eq!(lines("echo ab\\\ncd\n"), parse("echo abcd"));
+    }
 }
@@ -1,90 +1,41 @@
-use std::fs::{File, OpenOptions};
-
-use anyhow::{bail, Result};
 use winnow::{
-    ascii::{digit1, multispace0},
+    ascii::digit1,
     combinator::{alt, opt},
     ModalResult, Parser,
 };
 
-use crate::io::{PErr, PIn, POut, PType};
+use crate::ast::Redirect;
 
-use super::{command::command_token, RedirectToken, Stream};
+use super::{blank, command::word_token, Stream};
 
-pub(super) fn redirect_token(stream: &mut Stream) -> ModalResult<RedirectToken> {
+pub(super) fn redirect_token(stream: &mut Stream) -> ModalResult<Redirect> {
     alt((input, append_output, output)).parse_next(stream)
 }
 
-impl RedirectToken {
-    pub(super) fn into_pipe(self) -> Result<PType> {
-        match self {
-            RedirectToken::Input { n, word } => {
-                let file = File::open(word)?;
-                if n != 0 {
-                    bail!("only support stdin for redirect input, received {n}")
-                }
-                Ok(PType::In(PIn::File(file)))
-            }
-            RedirectToken::Output { n, word } => {
-                let file = OpenOptions::new()
-                    .create(true)
-                    .truncate(true)
-                    .append(false)
-                    .write(true)
-                    .open(word)?;
-                match n {
-                    1 => Ok(PType::Out(POut::File(file))),
-                    2 => Ok(PType::Err(PErr::File(file))),
-                    _ => bail!("invalid file descriptor {n}"),
-                }
-            }
-            RedirectToken::AppendOutput { n, word } => {
-                let file = OpenOptions::new().create(true).append(true).open(word)?;
-                match n {
-                    1 => Ok(PType::Out(POut::File(file))),
-                    2 => Ok(PType::Err(PErr::File(file))),
-                    _ => bail!("invalid file descriptor {n}"),
-                }
-            }
-        }
+/// File descriptor before a redirection operator, `default` without one.
+/// Digits too large for a descriptor fail, so that they are read as a word.
+fn descriptor(default: i32) -> impl FnMut(&mut Stream) -> ModalResult<i32> {
+    move |stream| {
+        opt(digit1.try_map(str::parse::<i32>))
+            .map(|n| n.unwrap_or(default))
+            .parse_next(stream)
     }
 }
 
-fn output(stream: &mut Stream) -> ModalResult<RedirectToken> {
-    let (_, n, _, _, _, word) = (
-        multispace0,
-        opt(digit1).map(|s| s.map(|s: &str| s.parse::<i32>().unwrap()).unwrap_or(1)),
-        ">",
-        opt("|"),
-        multispace0,
-        command_token,
-    )
-        .parse_next(stream)?;
-    Ok(RedirectToken::Output { n, word: word.0 })
+fn output(stream: &mut Stream) -> ModalResult<Redirect> {
+    let (n, _, _, _, word) =
+        (descriptor(1), ">", opt("|"), blank, word_token).parse_next(stream)?;
+    Ok(Redirect::Output { n, word })
 }
 
-fn append_output(stream: &mut Stream) -> ModalResult<RedirectToken> {
-    let (_, n, _, _, word) = (
-        multispace0,
-        opt(digit1).map(|s| s.map(|s: &str| s.parse::<i32>().unwrap()).unwrap_or(1)),
-        ">>",
-        multispace0,
-        command_token,
-    )
-        .parse_next(stream)?;
-    Ok(RedirectToken::AppendOutput { n, word: word.0 })
+fn append_output(stream: &mut Stream) -> ModalResult<Redirect> {
+    let (n, _, _, word) = (descriptor(1), ">>", blank, word_token).parse_next(stream)?;
+    Ok(Redirect::AppendOutput { n, word })
 }
 
-fn input(stream: &mut Stream) -> ModalResult<RedirectToken> {
-    let (_, n, _, _, word) = (
-        multispace0,
-        opt(digit1).map(|s| s.map(|s: &str| s.parse::<i32>().unwrap()).unwrap_or(0)),
-        "<",
-        multispace0,
-        command_token,
-    )
-        .parse_next(stream)?;
-    Ok(RedirectToken::Input { n, word: word.0 })
+fn input(stream: &mut Stream) -> ModalResult<Redirect> {
+    let (n, _, _, word) = (descriptor(0), "<", blank, word_token).parse_next(stream)?;
+    Ok(Redirect::Input { n, word })
 }
 
 #[cfg(test)]
@@ -96,35 +47,35 @@ mod test {
     fn test_output() {
         assert_eq!(
             redirect_token(&mut Stream::new(">word\n")).unwrap(),
-            RedirectToken::Output {
+            Redirect::Output {
                 n: 1,
                 word: "word".into()
             }
         );
         assert_eq!(
             redirect_token(&mut Stream::new("1>word\n")).unwrap(),
-            RedirectToken::Output {
+            Redirect::Output {
                 n: 1,
                 word: "word".into()
             }
         );
         assert_eq!(
             redirect_token(&mut Stream::new("2>word\n")).unwrap(),
-            RedirectToken::Output {
+            Redirect::Output {
                 n: 2,
                 word: "word".into()
             }
         );
         assert_eq!(
             redirect_token(&mut Stream::new(">|word\n")).unwrap(),
-            RedirectToken::Output {
+            Redirect::Output {
                 n: 1,
                 word: "word".into()
             }
         );
         assert_eq!(
             redirect_token(&mut Stream::new("> word\n")).unwrap(),
-            RedirectToken::Output {
+            Redirect::Output {
                 n: 1,
                 word: "word".into()
             }
@@ -135,28 +86,28 @@ mod test {
     fn test_append_output() {
         assert_eq!(
             redirect_token(&mut Stream::new(">>word\n")).unwrap(),
-            RedirectToken::AppendOutput {
+            Redirect::AppendOutput {
                 n: 1,
                 word: "word".into()
             }
         );
         assert_eq!(
             redirect_token(&mut Stream::new(">> word\n")).unwrap(),
-            RedirectToken::AppendOutput {
+            Redirect::AppendOutput {
                 n: 1,
                 word: "word".into()
             }
         );
         assert_eq!(
             redirect_token(&mut Stream::new("1>>word\n")).unwrap(),
-            RedirectToken::AppendOutput {
+            Redirect::AppendOutput {
                 n: 1,
                 word: "word".into()
             }
         );
         assert_eq!(
             redirect_token(&mut Stream::new("2>>word\n")).unwrap(),
-            RedirectToken::AppendOutput {
+            Redirect::AppendOutput {
                 n: 2,
                 word: "word".into()
             }
@@ -167,24 +118,30 @@ mod test {
     fn test_input() {
         assert_eq!(
             redirect_token(&mut Stream::new("<word\n")).unwrap(),
-            RedirectToken::Input {
+            Redirect::Input {
                 n: 0,
                 word: "word".into()
             }
         );
         assert_eq!(
             redirect_token(&mut Stream::new("< word \n")).unwrap(),
-            RedirectToken::Input {
+            Redirect::Input {
                 n: 0,
                 word: "word".into()
             }
         );
         assert_eq!(
             redirect_token(&mut Stream::new("2< word\n")).unwrap(),
-            RedirectToken::Input {
+            Redirect::Input {
                 n: 2,
                 word: "word".into()
             }
         );
     }
+
+    #[test]
+    fn test_descriptor_overflow() {
+        assert!(redirect_token(&mut Stream::new("99999999999>word\n")).is_err());
+        assert!(redirect_token(&mut Stream::new("99999999999\n")).is_err());
+    }
 }
@@ -1,21 +1,176 @@
-use std::{fs::OpenOptions, io::Write};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use anyhow::Result;
-use rustyline::{error::ReadlineError, CompletionType, Config, Editor};
+use rustyline::{
+    config::Configurer, error::ReadlineError, CompletionType, Config, EditMode, Editor,
+};
 
 use crate::{
+    command::{execute_input, execute_list},
     complete::{ShellCompleter, ShellHelper},
-    parse::StreamCommandParser,
+    io::{Io, PIn},
+    job::{catch_interrupt, clear_interrupt, init_job_control, interrupted, INTERRUPTED_STATUS},
+    parse::{parse_lines, parse_with_aliases, ParseError, StreamCommandParser},
+    state::{ShellOption, ShellState},
+    utils::ResolveError,
     HIST_FILE,
 };
 
-pub fn run_shell() -> Result<()> {
-    let config = Config::builder()
+#[derive(Debug, Default, Clone)]
+pub struct ShellConfig {
+    /// Print the syntax tree of every command instead of executing it
+    pub dump_ast: bool,
+    /// File to print in canonical form instead of running the shell, `--format`
+    pub format: Option<String>,
+    /// Name the shell was invoked with, `$0` unless it runs a script
+    pub name: String,
+    /// Script to execute instead of reading commands interactively
+    pub script: Option<String>,
+    /// Commands to execute instead of reading them, `-c`
+    pub command: Option<String>,
+    /// Read commands from stdin even with operands, `-s`
+    pub stdin: bool,
+    /// Behave as an interactive shell, `-i`
+    pub interactive: bool,
+    /// Behave as a login shell, `-l` or `--login`
+    pub login: bool,
+    /// Do not read the startup file of interactive shells, `--norc`
+    pub norc: bool,
+    /// Startup file read instead of the default one, `--rcfile`
+    pub rcfile: Option<String>,
+    /// Options set or unset on the command line, such as `-e` or `+o pipefail`
+    pub(crate) options: Vec<(ShellOption, bool)>,
+    /// Positional parameters, `$1` and after
+    pub args: Vec<String>,
+}
+
+impl ShellConfig {
+    /// Parse the command line of the shell, starting with the name it was invoked with.
+    ///
+    /// Options end at the first operand, `-` or `--`. The first operand is the
+    /// script to run, or with `-c` the commands, then `$0`. The rest are the
+    /// positional parameters.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<ShellConfig, String> {
+        let mut config = ShellConfig::default();
+        let mut args = args.into_iter();
+        config.name = args.next().unwrap_or_default();
+        let mut command = false;
+        let mut operands = vec![];
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-" | "--" => break,
+                "--dump-ast" => config.dump_ast = true,
+                "--format" => config.format = Some(required(&mut args, &arg)?),
+                "--login" => config.login = true,
+                "--norc" => config.norc = true,
+                "--rcfile" => config.rcfile = Some(required(&mut args, &arg)?),
+                long if long.starts_with("--") => return Err(format!("{long}: invalid option")),
+                flags if flags.len() > 1 && (flags.starts_with('-') || flags.starts_with('+')) => {
+                    let enabled = flags.starts_with('-');
+                    let sign = &flags[..1];
+                    for flag in flags[1..].chars() {
+                        match flag {
+                            'c' if enabled => command = true,
+                            's' if enabled => config.stdin = true,
+                            'i' if enabled => config.interactive = true,
+                            'l' if enabled => config.login = true,
+                            'o' => {
+                                let name = required(&mut args, &format!("{sign}o"))?;
+                                let option = ShellOption::from_str(&name)
+                                    .map_err(|_| format!("{name}: invalid option name"))?;
+                                config.options.push((option, enabled));
+                            }
+                            flag => match ShellOption::from_letter(flag) {
+                                Some(option) => config.options.push((option, enabled)),
+                                None => return Err(format!("{sign}{flag}: invalid option")),
+                            },
+                        }
+                    }
+                }
+                _ => {
+                    operands.push(arg);
+                    break;
+                }
+            }
+        }
+        operands.extend(args);
+
+        let mut operands = operands.into_iter();
+        if command {
+            config.command = Some(required(&mut operands, "-c")?);
+            if let Some(name) = operands.next() {
+                config.name = name;
+            }
+        } else if !config.stdin {
+            config.script = operands.next();
+        }
+        config.args = operands.collect();
+        Ok(config)
+    }
+}
+
+/// Argument of an option, such as the file of `--rcfile`
+fn required(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("{option}: option requires an argument"))
+}
+
+/// Profile read by every login shell
+const SYSTEM_PROFILE: &str = "/etc/profile";
+/// Profile of the user read by login shells, in their home directory
+const PROFILE: &str = ".profile";
+/// Startup file of interactive shells, in the home directory
+const RC_FILE: &str = ".codecraftersshrc";
+/// Read by a login shell when it exits, in the home directory
+const LOGOUT_FILE: &str = ".codecrafters_logout";
+
+/// Run the shell, returning its exit status
+pub fn run_shell(config: ShellConfig) -> Result<i32> {
+    let mut state = ShellState::default();
+    state.import_env();
+    state.set_shell_name(config.script.clone().unwrap_or(config.name.clone()));
+    state.set_positional(config.args.clone());
+    for &(option, enabled) in &config.options {
+        state.set_option(option, enabled);
+    }
+    // a login shell is usually started with a name such as `-sh`
+    state.set_login(config.login || config.name.starts_with('-'));
+    let interactive = config.interactive
+        || (config.command.is_none() && config.script.is_none() && io::stdin().is_terminal());
+    state.set_interactive(interactive);
+    state.set_input_flag(match (&config.command, &config.script) {
+        (Some(_), _) => Some('c'),
+        (None, Some(_)) => None,
+        (None, None) => Some('s'),
+    });
+    // line editing uses emacs bindings unless chosen on the command line
+    let edit_mode =
+        |(option, _): &(ShellOption, bool)| matches!(option, ShellOption::Emacs | ShellOption::Vi);
+    if interactive && !config.options.iter().any(edit_mode) {
+        state.set_option(ShellOption::Emacs, true);
+    }
+    read_startup_files(&mut state, &config, interactive)?;
+    if let Some(command) = &config.command {
+        return run_input(&mut state, command, "-c", &config);
+    }
+    if let Some(script) = &config.script {
+        return run_script(&mut state, script, &config);
+    }
+    if !config.interactive && !io::stdin().is_terminal() {
+        return run_stdin(&mut state, &config);
+    }
+
+    let config_rl = Config::builder()
         .completion_type(CompletionType::List)
         .build();
-    let mut rl = Editor::with_config(config)?;
+    let mut rl = Editor::with_config(config_rl)?;
     let h = ShellHelper {
-        completer: ShellCompleter {},
+        completer: ShellCompleter::default(),
     };
     rl.set_helper(Some(h));
 
@@ -26,23 +181,99 @@ pub fn run_shell() -> Result<()> {
         .open(HIST_FILE)
         .unwrap();
 
-    loop {
+    let io = Io::std();
+    if io::stdin().is_terminal() {
+        init_job_control()?;
+        catch_interrupt()?;
+        // unless disabled with `+m`
+        if !config.options.contains(&(ShellOption::Monitor, false)) {
+            state.set_option(ShellOption::Monitor, true);
+        }
+    }
+
+    // consecutive end of files ignored with ignoreeof
+    let mut ignored_eofs = 0;
+    'read: loop {
+        // report the jobs that finished since the last prompt
+        state.jobs_mut().poll()?;
+        if state.option(ShellOption::Monitor) {
+            for line in state.jobs_mut().remove_done() {
+                eprintln!("{line}");
+            }
+        }
+
+        if let Some(helper) = rl.helper_mut() {
+            helper
+                .completer
+                .set_path(state.var("PATH"), state.hash_resets());
+        }
+        rl.set_edit_mode(match state.option(ShellOption::Vi) {
+            true => EditMode::Vi,
+            false => EditMode::Emacs,
+        });
         let readline = rl.readline("$ ");
+        if !matches!(readline, Err(ReadlineError::Eof)) {
+            ignored_eofs = 0;
+        }
         match readline {
             Ok(line) => {
-                rl.add_history_entry(line.as_str())?;
-                let parser = StreamCommandParser::new(&line);
-                if !parser.is_empty() {
-                    hist_file.write_all(format!("{line}\r\n").as_bytes())?;
-                    let command = parser.finish()?;
-                    command.execute()?;
+                let mut input = line;
+                if StreamCommandParser::new(&input).is_empty() {
+                    rl.add_history_entry(input.as_str())?;
+                    continue;
+                }
+
+                // keep reading lines until the command is complete
+                let parsed = loop {
+                    match parse_lines(&input, state.aliases()) {
+                        Err(ParseError::Incomplete) => match rl.readline("> ") {
+                            Ok(line) => {
+                                input.push('\n');
+                                input.push_str(&line);
+                            }
+                            Err(ReadlineError::Interrupted) => {
+                                state.set_status(INTERRUPTED_STATUS);
+                                continue 'read;
+                            }
+                            Err(_) => break Err(ParseError::Incomplete),
+                        },
+                        parsed => break parsed,
+                    }
+                };
+
+                rl.add_history_entry(input.as_str())?;
+                hist_file.write_all(format!("{input}\r\n").as_bytes())?;
+                match parsed {
+                    Ok(list) if config.dump_ast => println!("{list:#?}"),
+                    Ok(list) => {
+                        // a Ctrl-C received at the prompt was already handled
+                        clear_interrupt();
+                        execute_list(&mut state, &list, &io)?;
+                        // the prompt goes after the ^C echoed by the terminal
+                        if interrupted() {
+                            eprintln!();
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("{err}");
+                        state.set_status(2);
+                    }
                 }
             }
-            Err(ReadlineError::Interrupted) => {
-                println!("CTRL-C");
-                break;
+            // Ctrl-C discards the line
+            Err(ReadlineError::Interrupted) => state.set_status(INTERRUPTED_STATUS),
+            Err(ReadlineError::Eof)
+                if state.option(ShellOption::Ignoreeof)
+                    && ignored_eofs < max_ignored_eofs(&state) =>
+            {
+                ignored_eofs += 1;
+                eprintln!("Use \"exit\" to leave the shell.");
             }
             Err(ReadlineError::Eof) => {
+                // the logout file does not change the exit status
+                let status = state.status();
+                logout(&mut state)?;
+                state.set_status(status);
                 break;
             }
             Err(err) => {
@@ -51,5 +282,179 @@ pub fn run_shell() -> Result<()> {
             }
         }
     }
+    Ok(state.status())
+}
+
+/// Number of consecutive end of files ignored before exiting, `IGNOREEOF` or 10
+fn max_ignored_eofs(state: &ShellState) -> usize {
+    state
+        .var("IGNOREEOF")
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(10)
+}
+
+/// Source the profiles of a login shell, then the rc file of an interactive
+/// shell: `--rcfile`, otherwise `$ENV`, otherwise `~/.codecraftersshrc`
+fn read_startup_files(
+    state: &mut ShellState,
+    config: &ShellConfig,
+    interactive: bool,
+) -> Result<()> {
+    if state.login() {
+        source_startup(state, Path::new(SYSTEM_PROFILE))?;
+        if let Some(profile) = home_file(state, PROFILE) {
+            source_startup(state, &profile)?;
+        }
+    }
+    if interactive && !config.norc {
+        let rc_file = config
+            .rcfile
+            .clone()
+            .or_else(|| state.var("ENV").filter(|env| !env.is_empty()))
+            .map(PathBuf::from)
+            .or_else(|| home_file(state, RC_FILE));
+        if let Some(rc_file) = rc_file {
+            source_startup(state, &rc_file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Source the logout file if the shell is a login shell
+pub(crate) fn logout(state: &mut ShellState) -> Result<()> {
+    if state.login() {
+        // only once, even if the logout file itself calls `exit`
+        state.set_login(false);
+        if let Some(logout_file) = home_file(state, LOGOUT_FILE) {
+            source_startup(state, &logout_file)?;
+        }
+    }
     Ok(())
 }
+
+/// Leave the shell with `status`, sourcing the logout file of a login shell
+/// first
+pub(crate) fn exit_shell(state: &mut ShellState, status: i32) -> ! {
+    if let Err(err) = logout(state) {
+        eprintln!("{err:#}");
+    }
+    std::process::exit(status)
+}
+
+fn home_file(state: &ShellState, name: &str) -> Option<PathBuf> {
+    state.var("HOME").map(|home| Path::new(&home).join(name))
+}
+
+/// Execute a startup file like `source`, a missing file is skipped
+fn source_startup(state: &mut ShellState, path: &Path) -> Result<()> {
+    let Ok(input) = fs::read_to_string(path) else {
+        return Ok(());
+    };
+    state.enter_source();
+    let result = run_input(
+        state,
+        &input,
+        &path.display().to_string(),
+        &ShellConfig::default(),
+    );
+    state.leave_source();
+    result.map(|_| ())
+}
+
+/// Execute the commands read from a stdin that is not a terminal, without
+/// prompt, history or completion. The exit status is the one of the last command.
+fn run_stdin(state: &mut ShellState, config: &ShellConfig) -> Result<i32> {
+    let io = Io::std();
+    let mut stdin = PIn::std();
+    let mut input = String::new();
+    // the input is read a line at a time, the rest is left to the commands
+    while let Some(line) = read_line(&mut stdin)? {
+        input.push_str(&line);
+        match parse_lines(&input, state.aliases()) {
+            Err(ParseError::Incomplete) => continue,
+            Ok(list) if config.dump_ast => println!("{list:#?}"),
+            Ok(list) => {
+                execute_list(state, &list, &io)?;
+                state.jobs_mut().poll()?;
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                state.set_status(2);
+            }
+        }
+        input.clear();
+    }
+    // a last line continued by a backslash still ends the command
+    if !input.is_empty() {
+        match parse_with_aliases(&input, state.aliases()) {
+            Ok(list) if config.dump_ast => println!("{list:#?}"),
+            Ok(list) => {
+                execute_list(state, &list, &io)?;
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                state.set_status(2);
+            }
+        }
+    }
+    Ok(state.status())
+}
+
+/// Next line of the input with its newline, `None` at the end of the input
+fn read_line(stdin: &mut PIn) -> Result<Option<String>> {
+    let mut line = vec![];
+    while let Some(byte) = stdin.read_byte()? {
+        line.push(byte);
+        if byte == b'\n' {
+            break;
+        }
+    }
+    match line.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(String::from_utf8_lossy(&line).into_owned())),
+    }
+}
+
+/// Execute a script file, its exit status is the one of its last command
+fn run_script(state: &mut ShellState, path: &str, config: &ShellConfig) -> Result<i32> {
+    let input = match fs::read_to_string(path) {
+        Ok(input) => input,
+        Err(err) => {
+            let error = match err.kind() {
+                io::ErrorKind::NotFound => ResolveError::NoSuchFile,
+                io::ErrorKind::PermissionDenied => ResolveError::PermissionDenied,
+                io::ErrorKind::IsADirectory => ResolveError::IsDirectory,
+                _ => return Err(anyhow::Error::new(err).context(path.to_string())),
+            };
+            eprintln!("{path}: {error}");
+            return Ok(error.status());
+        }
+    };
+
+    run_input(state, &input, path, config)
+}
+
+/// Execute every command of `input`, syntax errors are reported with `source`
+/// and the line number, and stop the execution
+fn run_input(
+    state: &mut ShellState,
+    input: &str,
+    source: &str,
+    config: &ShellConfig,
+) -> Result<i32> {
+    if config.dump_ast {
+        match parse_with_aliases(input, state.aliases()) {
+            Ok(list) => println!("{list:#?}"),
+            Err(err) => eprintln!("{source}: {err}"),
+        }
+        return Ok(0);
+    }
+    match execute_input(state, input, &Io::std())? {
+        Ok(status) => Ok(status),
+        Err((line, err)) => {
+            eprintln!("{source}: line {line}: {err}");
+            state.set_status(2);
+            Ok(2)
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use strum::{AsRefStr, EnumIter, EnumString};
+
+use crate::{
+    job::JobTable,
+    utils::{resolve_command, ResolveError},
+};
+
+/// Options set with `set -o name`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumString, EnumIter, AsRefStr)]
+pub(crate) enum ShellOption {
+    /// Emacs key bindings when editing a line, the default
+    #[strum(serialize = "emacs")]
+    Emacs,
+    /// `-e`, exit when a command fails outside of a condition
+    #[strum(serialize = "errexit")]
+    Errexit,
+    /// An interactive shell does not exit on end of file, up to `IGNOREEOF` times
+    #[strum(serialize = "ignoreeof")]
+    Ignoreeof,
+    /// `-m`, job control, each pipeline runs in its own process group
+    #[strum(serialize = "monitor")]
+    Monitor,
+    /// `-f`, no pathname expansion
+    #[strum(serialize = "noglob")]
+    Noglob,
+    /// `-u`, expanding an unset variable is an error
+    #[strum(serialize = "nounset")]
+    Nounset,
+    /// The status of a pipeline is the one of its last failing command
+    #[strum(serialize = "pipefail")]
+    Pipefail,
+    /// Vi key bindings when editing a line
+    #[strum(serialize = "vi")]
+    Vi,
+    /// `-x`, print every command before executing it
+    #[strum(serialize = "xtrace")]
+    Xtrace,
+}
+
+impl ShellOption {
+    /// Option set by a single letter flag, such as `-e`
+    pub fn from_letter(letter: char) -> Option<ShellOption> {
+        match letter {
+            'e' => Some(ShellOption::Errexit),
+            'f' => Some(ShellOption::Noglob),
+            'm' => Some(ShellOption::Monitor),
+            'u' => Some(ShellOption::Nounset),
+            'x' => Some(ShellOption::Xtrace),
+            _ => None,
+        }
+    }
+
+    /// Single letter flag of the option, the reverse of [`Self::from_letter`]
+    pub fn letter(self) -> Option<char> {
+        match self {
+            ShellOption::Errexit => Some('e'),
+            ShellOption::Noglob => Some('f'),
+            ShellOption::Monitor => Some('m'),
+            ShellOption::Nounset => Some('u'),
+            ShellOption::Xtrace => Some('x'),
+            _ => None,
+        }
+    }
+}
+
+/// Options set with `shopt -s name`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumString, EnumIter, AsRefStr)]
+pub(crate) enum ShoptOption {
+    /// `cd` corrects small spelling errors in directory names
+    #[strum(serialize = "cdspell")]
+    Cdspell,
+    /// Pathname expansion matches names starting with a dot
+    #[strum(serialize = "dotglob")]
+    Dotglob,
+    /// `**` matches directories recursively
+    #[strum(serialize = "globstar")]
+    Globstar,
+    /// A pattern without match expands to nothing instead of itself
+    #[strum(serialize = "nullglob")]
+    Nullglob,
+}
+
+/// State kept by the shell between commands
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ShellState {
+    variables: HashMap<String, String>,
+    /// Names of the variables passed to child processes, set or not
+    exported: BTreeSet<String>,
+    /// Indexed arrays, such as `BASH_REMATCH`
+    arrays: HashMap<String, Vec<String>>,
+    aliases: BTreeMap<String, String>,
+    options: BTreeSet<ShellOption>,
+    shopt_options: BTreeSet<ShoptOption>,
+    /// Exit status of the last pipeline, `$?`
+    status: i32,
+    /// Pid of the last background job, `$!`
+    background_pid: Option<i32>,
+    jobs: JobTable,
+    /// Remembered locations of commands found in PATH, see `hash`
+    hashed: BTreeMap<String, HashedCommand>,
+    /// Number of times the hash table was cleared
+    hash_resets: usize,
+    /// `$0`, the name of the shell or of the script it runs
+    shell_name: String,
+    /// Positional parameters, `$1` and after
+    positional: Vec<String>,
+    /// Number of conditions being evaluated, where errexit does not apply
+    conditions: usize,
+    /// Number of files being executed by `source`, where `return` is allowed
+    sources: usize,
+    /// Status given to `return`, set until the sourced file stops
+    returned: Option<i32>,
+    /// A login shell reads the logout file when it exits
+    login: bool,
+    /// Reads commands from a user, errors such as unset variables do not exit
+    interactive: bool,
+    /// `c` when running the commands of `-c`, `s` when reading them from stdin
+    input_flag: Option<char>,
+}
+
+/// Location of a command in the hash table
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HashedCommand {
+    pub path: PathBuf,
+    /// Number of times the command was run from this location
+    pub hits: usize,
+}
+
+impl ShellState {
+    /// Import the environment of the shell as exported variables
+    pub fn import_env(&mut self) {
+        for (name, value) in std::env::vars() {
+            self.exported.insert(name.clone());
+            self.variables.insert(name, value);
+        }
+    }
+
+    /// Value of a shell variable, for an array it is the first element
+    pub fn var(&self, name: &str) -> Option<String> {
+        match self.arrays.get(name) {
+            Some(array) => array.first().cloned(),
+            None => self.variables.get(name).cloned(),
+        }
+    }
+
+    /// Set a variable, it stays exported if it was
+    pub fn set_var(&mut self, name: &str, value: String) {
+        self.path_changed(name);
+        self.arrays.remove(name);
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// Remove a variable, with its export attribute
+    pub fn unset_var(&mut self, name: &str) {
+        self.path_changed(name);
+        self.variables.remove(name);
+        self.arrays.remove(name);
+        self.exported.remove(name);
+    }
+
+    /// Scalar variables sorted by name
+    pub fn vars(&self) -> BTreeMap<&str, &str> {
+        self.variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect()
+    }
+
+    pub fn arrays(&self) -> BTreeMap<&str, &[String]> {
+        self.arrays
+            .iter()
+            .map(|(name, values)| (name.as_str(), values.as_slice()))
+            .collect()
+    }
+
+    /// Pass the variable to child processes, now and once it is set
+    pub fn export(&mut self, name: &str) {
+        self.exported.insert(name.to_string());
+    }
+
+    pub fn unexport(&mut self, name: &str) {
+        self.exported.remove(name);
+    }
+
+    /// Environment of child processes, the exported variables that are set
+    pub fn exported_vars(&self) -> Vec<(&str, &str)> {
+        self.exported
+            .iter()
+            .filter_map(|name| Some((name.as_str(), self.variables.get(name)?.as_str())))
+            .collect()
+    }
+
+    /// Forget the locations of commands when PATH changes
+    fn path_changed(&mut self, name: &str) {
+        if name == "PATH" {
+            self.clear_hashed();
+        }
+    }
+
+    /// Elements of an array, a scalar variable is an array of one element
+    pub fn array(&self, name: &str) -> Vec<String> {
+        match self.arrays.get(name) {
+            Some(array) => array.clone(),
+            None => self.var(name).into_iter().collect(),
+        }
+    }
+
+    pub fn set_array(&mut self, name: &str, values: Vec<String>) {
+        self.path_changed(name);
+        self.variables.remove(name);
+        self.arrays.insert(name.to_string(), values);
+    }
+
+    pub fn option(&self, option: ShellOption) -> bool {
+        self.options.contains(&option)
+    }
+
+    /// Set an option, enabling `vi` disables `emacs` and the other way around
+    pub fn set_option(&mut self, option: ShellOption, enabled: bool) {
+        match enabled {
+            true => self.options.insert(option),
+            false => self.options.remove(&option),
+        };
+        let other = match option {
+            ShellOption::Emacs => ShellOption::Vi,
+            ShellOption::Vi => ShellOption::Emacs,
+            _ => return,
+        };
+        if enabled {
+            self.options.remove(&other);
+        }
+    }
+
+    pub fn shopt(&self, option: ShoptOption) -> bool {
+        self.shopt_options.contains(&option)
+    }
+
+    pub fn set_shopt(&mut self, option: ShoptOption, enabled: bool) {
+        match enabled {
+            true => self.shopt_options.insert(option),
+            false => self.shopt_options.remove(&option),
+        };
+    }
+
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: i32) {
+        self.status = status;
+    }
+
+    pub fn background_pid(&self) -> Option<i32> {
+        self.background_pid
+    }
+
+    pub fn set_background_pid(&mut self, pid: i32) {
+        self.background_pid = Some(pid);
+    }
+
+    pub fn jobs(&self) -> &JobTable {
+        &self.jobs
+    }
+
+    pub fn jobs_mut(&mut self) -> &mut JobTable {
+        &mut self.jobs
+    }
+
+    /// Executable run for a command name, remembered in the hash table the
+    /// first time it is found in PATH
+    pub fn find_command(&mut self, name: &str) -> Result<PathBuf, ResolveError> {
+        if name.contains('/') {
+            return resolve_command(name, None);
+        }
+        // the location is searched again if the file was removed
+        if let Some(hashed) = self.hashed.get_mut(name) {
+            if hashed.path.is_file() {
+                hashed.hits += 1;
+                return Ok(hashed.path.clone());
+            }
+        }
+        let path = resolve_command(name, self.var("PATH").as_deref())?;
+        let hashed = HashedCommand {
+            path: path.clone(),
+            hits: 1,
+        };
+        self.hashed.insert(name.to_string(), hashed);
+        Ok(path)
+    }
+
+    pub fn hashed(&self) -> &BTreeMap<String, HashedCommand> {
+        &self.hashed
+    }
+
+    /// Remember `path` as the location of `name`, without running it
+    pub fn hash(&mut self, name: &str, path: PathBuf) {
+        let hashed = HashedCommand { path, hits: 0 };
+        self.hashed.insert(name.to_string(), hashed);
+    }
+
+    /// Forget the location of `name`, returning whether it was remembered
+    pub fn unhash(&mut self, name: &str) -> bool {
+        self.hashed.remove(name).is_some()
+    }
+
+    pub fn clear_hashed(&mut self) {
+        self.hashed.clear();
+        self.hash_resets += 1;
+    }
+
+    /// Number of times the hash table was cleared, by `hash -r` or a change
+    /// of PATH
+    pub fn hash_resets(&self) -> usize {
+        self.hash_resets
+    }
+
+    /// Location of a hashed command
+    pub fn hashed_path(&self, name: &str) -> Option<&Path> {
+        self.hashed.get(name).map(|hashed| hashed.path.as_path())
+    }
+
+    pub fn shell_name(&self) -> &str {
+        &self.shell_name
+    }
+
+    pub fn set_shell_name(&mut self, name: String) {
+        self.shell_name = name;
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    pub fn set_positional(&mut self, values: Vec<String>) {
+        self.positional = values;
+    }
+
+    /// Drop the first `count` positional parameters, unless there are fewer
+    pub fn shift(&mut self, count: usize) -> bool {
+        if count > self.positional.len() {
+            return false;
+        }
+        self.positional.drain(..count);
+        true
+    }
+
+    /// Evaluate a condition, such as the one of `if`, until [`Self::leave_condition`]
+    pub fn enter_condition(&mut self) {
+        self.conditions += 1;
+    }
+
+    pub fn leave_condition(&mut self) {
+        self.conditions -= 1;
+    }
+
+    pub fn in_condition(&self) -> bool {
+        self.conditions > 0
+    }
+
+    /// Execute a sourced file, until [`Self::leave_source`]
+    pub fn enter_source(&mut self) {
+        self.sources += 1;
+    }
+
+    /// Stop executing a sourced file, returning the status given to `return`
+    pub fn leave_source(&mut self) -> Option<i32> {
+        self.sources -= 1;
+        self.returned.take()
+    }
+
+    pub fn in_source(&self) -> bool {
+        self.sources > 0
+    }
+
+    /// Stop executing the sourced file with `status`
+    pub fn set_returned(&mut self, status: i32) {
+        self.returned = Some(status);
+    }
+
+    /// Whether `return` was called, every enclosing list and loop stops
+    pub fn returned(&self) -> bool {
+        self.returned.is_some()
+    }
+
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
+
+    pub fn set_input_flag(&mut self, flag: Option<char>) {
+        self.input_flag = flag;
+    }
+
+    /// Flags of the enabled options and of how the shell was invoked, `$-`
+    pub fn flags(&self) -> String {
+        let options = self.options.iter().filter_map(|option| option.letter());
+        let interactive = self.interactive.then_some('i');
+        options.chain(interactive).chain(self.input_flag).collect()
+    }
+
+    pub fn login(&self) -> bool {
+        self.login
+    }
+
+    pub fn set_login(&mut self, login: bool) {
+        self.login = login;
+    }
+
+    pub fn aliases(&self) -> &BTreeMap<String, String> {
+        &self.aliases
+    }
+
+    pub fn aliases_mut(&mut self) -> &mut BTreeMap<String, String> {
+        &mut self.aliases
+    }
+}
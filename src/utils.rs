@@ -1,14 +1,152 @@
-use std::path::PathBuf;
-
-use anyhow::Context;
-use anyhow::Result;
-
-pub(crate) fn path_lookup_exact(name: &str) -> Result<PathBuf> {
-    let paths = std::env::var("PATH")?;
-    let path = paths
-        .split(":")
-        .map(|path| PathBuf::from(path).join(name))
-        .find(|path| path.is_file())
-        .with_context(|| format!("missing executable file command, {name}"))?;
-    Ok(path)
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+use thiserror::Error;
+
+/// Why a command name does not resolve to an executable file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Error)]
+pub(crate) enum ResolveError {
+    /// No file with the name in any directory of PATH
+    #[default]
+    #[error("command not found")]
+    NotFound,
+    #[error("No such file or directory")]
+    NoSuchFile,
+    #[error("Permission denied")]
+    PermissionDenied,
+    #[error("Is a directory")]
+    IsDirectory,
+}
+
+impl ResolveError {
+    /// Exit status of the command, 126 if it was found but cannot be executed
+    pub fn status(self) -> i32 {
+        match self {
+            ResolveError::NotFound | ResolveError::NoSuchFile => 127,
+            ResolveError::PermissionDenied | ResolveError::IsDirectory => 126,
+        }
+    }
+}
+
+/// Path of the executable run for a command name.
+///
+/// A name containing a slash is a path itself, otherwise it is searched in
+/// every directory of `paths`, the value of PATH, where an empty entry is the
+/// current directory. A file without the execute bit is only used when no
+/// executable comes after it, and fails to execute.
+pub(crate) fn resolve_command(name: &str, paths: Option<&str>) -> Result<PathBuf, ResolveError> {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return check_executable(&path).map(|_| path);
+    }
+
+    let Some(paths) = paths else {
+        return Err(ResolveError::NotFound);
+    };
+    let mut fallback = None;
+    for dir in paths.split(':') {
+        let dir = if dir.is_empty() { "." } else { dir };
+        let path = Path::new(dir).join(name);
+        match check_executable(&path) {
+            Ok(()) => return Ok(path),
+            Err(ResolveError::PermissionDenied) => {
+                fallback.get_or_insert(path);
+            }
+            Err(_) => {}
+        }
+    }
+    fallback.ok_or(ResolveError::NotFound)
+}
+
+fn check_executable(path: &Path) -> Result<(), ResolveError> {
+    let metadata = fs::metadata(path).map_err(|_| ResolveError::NoSuchFile)?;
+    if metadata.is_dir() {
+        return Err(ResolveError::IsDirectory);
+    }
+    match metadata.permissions().mode() & 0o111 {
+        0 => Err(ResolveError::PermissionDenied),
+        _ => Ok(()),
+    }
+}
+
+/// Path of a file read by `source`, the first one named `name` in a directory
+/// of `paths`, otherwise `name` itself
+pub(crate) fn find_file(name: &str, paths: Option<&str>) -> PathBuf {
+    if !name.contains('/') {
+        let found = paths
+            .into_iter()
+            .flat_map(|paths| paths.split(':'))
+            .map(|dir| Path::new(if dir.is_empty() { "." } else { dir }).join(name))
+            .find(|path| path.is_file());
+        if let Some(path) = found {
+            return path;
+        }
+    }
+    PathBuf::from(name)
+}
+
+/// Directory named like `path` up to a small spelling error in each of its
+/// components, as corrected by `cd` with cdspell
+pub(crate) fn correct_spelling(path: &str) -> Option<PathBuf> {
+    let mut corrected = PathBuf::new();
+    for component in Path::new(path).components() {
+        let Component::Normal(name) = component else {
+            corrected.push(component);
+            continue;
+        };
+        if corrected.join(name).is_dir() {
+            corrected.push(name);
+            continue;
+        }
+        let dir = match corrected.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => corrected.as_path(),
+        };
+        let name = name.to_str()?;
+        let found = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|entry| is_misspelling(name, entry))
+            .min()?;
+        corrected.push(found);
+    }
+    Some(corrected)
+}
+
+/// Whether `a` and `b` differ by one character changed, added or removed, or
+/// by two adjacent characters transposed
+fn is_misspelling(a: &str, b: &str) -> bool {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    match (a.len(), b.len()) {
+        (0, 0) => false,
+        (x, y) if x == y => {
+            a[1..] == b[1..] || (x >= 2 && a[0] == b[1] && a[1] == b[0] && a[2..] == b[2..])
+        }
+        (x, y) if x == y + 1 => a[1..] == *b,
+        (x, y) if x + 1 == y => *a == b[1..],
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn misspellings() {
+        assert!(is_misspelling("tmp", "tnp"));
+        assert!(is_misspelling("tmp", "tpm"));
+        assert!(is_misspelling("tmp", "tmpp"));
+        assert!(is_misspelling("tmp", "tp"));
+        assert!(!is_misspelling("tmp", "tmp"));
+        assert!(!is_misspelling("tmp", "pmt"));
+        assert!(!is_misspelling("tmp", "t"));
+    }
 }
@@ -6,16 +6,24 @@ use std::{
 };
 
 use assert_cmd::Command;
+use rexpect::session::PtySession;
 use tempfile::tempdir;
 
 #[derive(Default)]
 struct TestOption {
     env: Option<(String, String)>,
     current_dir: Option<PathBuf>,
+    args: Vec<String>,
     err: bool,
+    /// Exit status of the shell, the one of the last command
+    code: i32,
 }
 
 impl TestOption {
+    fn arg(mut self, arg: &str) -> TestOption {
+        self.args.push(arg.into());
+        self
+    }
     fn env(mut self, k: &str, v: &str) -> TestOption {
         self.env = Some((k.into(), v.into()));
         self
@@ -31,8 +39,13 @@ impl TestOption {
         self.err = true;
         self
     }
+    fn code(mut self, code: i32) -> TestOption {
+        self.code = code;
+        self
+    }
 }
 
+#[track_caller]
 fn check_contains(input: &str, expected: &str, opt: TestOption) {
     let input = input.to_string();
     let expected = expected.trim().to_string() + "\n";
@@ -45,7 +58,8 @@ fn check_contains(input: &str, expected: &str, opt: TestOption) {
     if let Some(dir) = &opt.current_dir {
         command = command.current_dir(dir);
     }
-    let assert = command.write_stdin(input).assert().success();
+    command = command.args(&opt.args);
+    let assert = command.write_stdin(input).assert().code(opt.code);
 
     if opt.err {
         assert.stderr(predicate::str::ends_with(expected));
@@ -63,7 +77,10 @@ fn check_complete(input: &str, expected: &str) -> Result<()> {
     p.flush()?;
     p.exp_string(expected)?;
 
+    // Ctrl-C discards the line, exit at the next empty prompt
     p.send_control('c')?;
+    p.exp_string("$ \r")?;
+    p.send_line("exit")?;
     p.exp_eof()?;
     Ok(())
 }
@@ -77,7 +94,10 @@ fn check_complete_exec(input: &str, expected: &str) -> Result<()> {
     p.flush()?;
     p.exp_string(expected)?;
 
+    // Ctrl-C discards the line, exit at the next empty prompt
     p.send_control('c')?;
+    p.exp_string("$ \r")?;
+    p.send_line("exit")?;
     p.exp_eof()?;
     Ok(())
 }
@@ -87,7 +107,7 @@ fn handle_invalid_commands() {
     check_contains(
         "some_command",
         "some_command: command not found",
-        TestOption::default(),
+        TestOption::default().code(127),
     );
 }
 
@@ -102,7 +122,7 @@ command2
 command1: command not found
 command2: command not found
 "#,
-        TestOption::default(),
+        TestOption::default().code(127),
     )
 }
 
@@ -158,7 +178,7 @@ type is a shell builtin
 history is a shell builtin
 invalid_command: not found
 "#,
-        TestOption::no_path(),
+        TestOption::no_path().code(1),
     )
 }
 
@@ -174,7 +194,7 @@ exit is a shell builtin
 type is a shell builtin
 invalid_command: not found
 "#,
-        TestOption::no_path(),
+        TestOption::no_path().code(1),
     )
 }
 
@@ -267,7 +287,7 @@ cd: {}: No such file or directory
 "#,
             level2_non_existed.display(),
         ),
-        TestOption::no_path().err(),
+        TestOption::no_path().err().code(1),
     )
 }
 
@@ -567,10 +587,63 @@ fn complete_path() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn complete_path_changes() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp_dir = tempdir()?;
+    let bin = tmp_dir.path().join("bin");
+    fs::create_dir(&bin)?;
+    fs::create_dir(tmp_dir.path().join("a"))?;
+    for file in [bin.join("plain"), tmp_dir.path().join("a/acmd")] {
+        fs::write(&file, "#!/bin/sh\n")?;
+    }
+    fs::set_permissions(
+        tmp_dir.path().join("a/acmd"),
+        fs::Permissions::from_mode(0o755),
+    )?;
+
+    let command = Command::cargo_bin("codecrafters-shell")?;
+    let path = Path::new(command.get_program()).to_str().unwrap();
+    let mut p = rexpect::spawn(path, Some(2000))?;
+    let complete = |p: &mut PtySession, input: &str, expected: &str| -> Result<()> {
+        // typeahead is discarded while the previous command runs
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        p.send(input)?;
+        p.flush()?;
+        p.exp_string(expected)?;
+        p.send_control('c')?;
+        p.exp_string("$ \r")?;
+        Ok(())
+    };
+
+    // an empty entry of PATH is the current directory
+    p.send_line(&format!(
+        "PATH={}:; cd {}",
+        bin.display(),
+        tmp_dir.path().display()
+    ))?;
+    complete(&mut p, "plai\t", "\x07")?;
+    // the executables are listed again after `hash -r`
+    fs::set_permissions(bin.join("plain"), fs::Permissions::from_mode(0o755))?;
+    p.send_line("hash -r")?;
+    complete(&mut p, "plai\t", "plain ")?;
+    // or when a directory of PATH changes
+    fs::write(bin.join("newcmd"), "#!/bin/sh\n")?;
+    fs::set_permissions(bin.join("newcmd"), fs::Permissions::from_mode(0o755))?;
+    complete(&mut p, "newc\t", "newcmd ")?;
+    p.send_line("cd a")?;
+    complete(&mut p, "acm\t", "acmd ")?;
+
+    p.send_line("exit")?;
+    p.exp_eof()?;
+    Ok(())
+}
+
 #[test]
 fn complete_many() -> Result<()> {
     check_complete("exp\t\t", "\x07")?;
-    check_complete("exp\t\t", "expand  expiry  expr")?;
+    check_complete("exp\t\t", "expand  expiry  export  expr")?;
     Ok(())
 }
 
@@ -653,6 +726,30 @@ cat {} | wc
     )
 }
 
+#[test]
+fn pipe_large_output() {
+    let tmp_dir = tempdir().unwrap();
+    let output = tmp_dir.path().join("output");
+    check_contains(
+        &format!(
+            r#"
+seq 1 500000 | cat | tail -n 1
+{{ echo first; seq 1 100000; }} | cat | head -n 2
+seq 1 100000 > {} | wc -l
+wc -l < {}
+"#,
+            output.display(),
+            output.display(),
+        ),
+        "500000
+first
+1
+100000
+100000",
+        TestOption::default(),
+    )
+}
+
 #[test]
 fn history() {
     check_contains(
@@ -671,7 +768,7 @@ invalid_command: command not found
     3 invalid_command
     4 history
 "#,
-        TestOption::no_path(),
+        TestOption::no_path().arg("-i"),
     )
 }
 
@@ -690,7 +787,1031 @@ world
 invalid_command: command not found
     3 invalid_command
     4 history 2
+"#,
+        TestOption::no_path().arg("-i"),
+    )
+}
+
+#[test]
+fn and_or_lists() {
+    check_contains(
+        r#"
+echo one && echo two
+invalid_command || echo three
+invalid_command && echo four; echo five
+"#,
+        r#"
+one
+two
+invalid_command: command not found
+three
+invalid_command: command not found
+five
+"#,
+        TestOption::no_path(),
+    )
+}
+
+#[test]
+fn if_clause() {
+    check_contains(
+        r#"
+if invalid_command; then echo one; elif echo two; then echo three; else echo four; fi
+if echo five
+then
+  echo six
+fi
+"#,
+        r#"
+invalid_command: command not found
+two
+three
+five
+six
+"#,
+        TestOption::no_path(),
+    )
+}
+
+#[test]
+fn for_and_case() {
+    check_contains(
+        r#"
+for name in one 'two three' four; do
+  case $name in
+    one) echo "first $name";;
+    *' '*) echo "spaced $name";;
+    *) echo "other $name";;
+  esac
+done
+"#,
+        r#"
+first one
+spaced two three
+other four
+"#,
+        TestOption::no_path(),
+    )
+}
+
+#[test]
+fn brace_group_pipe() {
+    check_contains(
+        "{ echo hello; echo world; } | wc -l",
+        "2",
+        TestOption::default(),
+    )
+}
+
+#[test]
+fn subshell_keeps_directory() {
+    let tmp_dir = tempdir().unwrap();
+
+    check_contains(
+        &format!(
+            "(cd {}; pwd); pwd",
+            tmp_dir.path().parent().unwrap().display()
+        ),
+        &format!(
+            r#"
+{}
+{}
+"#,
+            tmp_dir.path().parent().unwrap().display(),
+            tmp_dir.path().display(),
+        ),
+        TestOption::no_path().current_dir(tmp_dir.path().to_path_buf()),
+    )
+}
+
+#[test]
+fn subshell_exit() {
+    check_contains(
+        r#"
+(exit 3); echo after $?
+x=1; (x=2; echo $x); echo $x
+(set -u; echo $missing; echo unreachable); echo unset $?
+(echo a; echo b) | cat
+"#,
+        r#"
+after 3
+2
+1
+unset 127
+a
+b
+"#,
+        TestOption::default(),
+    )
+}
+
+#[test]
+fn background_compound() {
+    check_contains(
+        r#"
+{ sleep 0.2; echo x; } & echo started
+true && echo y &
+sleep 0.5
+"#,
+        r#"
+started
+y
+x
+"#,
+        TestOption::default(),
+    )
+}
+
+#[test]
+fn parameter_expansion() {
+    check_contains(
+        r#"
+echo $GREETING "${GREETING}s" '$GREETING'
+"#,
+        "hello hellos $GREETING",
+        TestOption::no_path().env("GREETING", "hello"),
+    )
+}
+
+#[test]
+fn syntax_error() {
+    check_contains(
+        "echo a )",
+        "syntax error near unexpected token `)'",
+        TestOption::no_path().err().code(2),
+    );
+    // an interactive shell goes on with the next command
+    check_contains("echo a )\necho $?", "2", TestOption::no_path().arg("-i"))
+}
+
+#[test]
+fn dump_ast() {
+    check_contains(
+        "echo hi | cat",
+        r#"
+                    ],
+                },
+                rest: [],
+            },
+            background: false,
+            comments: [],
+            comment: None,
+        },
+    ],
+    comments: [],
+}
+"#,
+        TestOption::no_path().arg("--dump-ast"),
+    )
+}
+
+#[test]
+fn format_script() {
+    let tmp_dir = tempdir().unwrap();
+    let script = tmp_dir.path().join("script.sh");
+    fs::write(
+        &script,
+        "# greet\nfor x in a 'b c';do echo \"$x\">>out;done|cat\n",
+    )
+    .unwrap();
+
+    check_contains(
+        "",
+        r#"
+# greet
+for x in a 'b c'; do
+  echo "$x" >>out
+done | cat
+"#,
+        TestOption::default()
+            .arg("--format")
+            .arg(script.to_str().unwrap()),
+    )
+}
+
+#[test]
+fn assignments() {
+    check_contains(
+        "GREETING=hi sh -c 'echo $GREETING'\nsh -c 'echo [$GREETING]'",
+        "hi\n[]",
+        TestOption::default(),
+    );
+    check_contains(
+        "name=world; echo \"hello $name\"; sh -c 'echo [$name]'",
+        "hello world\n[]",
+        TestOption::default(),
+    )
+}
+
+#[test]
+fn alias() {
+    check_contains(
+        "alias greet='echo hello' say='echo '\ngreet world\nsay greet\nalias\nunalias greet\ngreet",
+        r#"
+hello world
+echo hello
+alias greet='echo hello'
+alias say='echo '
+greet: command not found
+"#,
+        TestOption::no_path().code(127),
+    )
+}
+
+#[test]
+fn ansi_c_quoting() {
+    check_contains(
+        "echo $'one\\ttwo' | cut -d$'\\t' -f2\necho $'it\\'s' $'\\x41\\u00e9'",
+        "two\nit's Aé",
+        TestOption::default(),
+    )
+}
+
+#[test]
+fn field_splitting() {
+    check_contains(
+        "v='a  b'\nfor x in $v \"$v\" '' $empty x$v; do echo \"[$x]\"; done\nIFS=:; v=one:two:; for x in $v; do echo \"<$x>\"; done",
+        r#"
+[a]
+[b]
+[a  b]
+[]
+[xa]
+[b]
+<one>
+<two>
+"#,
+        TestOption::no_path(),
+    )
+}
+
+#[test]
+fn pathname_expansion() {
+    let tmp_dir = tempdir().unwrap();
+    File::create(tmp_dir.path().join("b.txt")).unwrap();
+    File::create(tmp_dir.path().join("a.txt")).unwrap();
+    File::create(tmp_dir.path().join(".hidden.txt")).unwrap();
+
+    check_contains(
+        "echo *.txt '*'.txt \"*.txt\" *.none",
+        "a.txt b.txt *.txt *.txt *.none",
+        TestOption::no_path().current_dir(tmp_dir.into_path()),
+    )
+}
+
+#[test]
+fn negation() {
+    check_contains(
+        "! false && echo negated\n! echo hi | cat || echo failed",
+        "negated\nhi\nfailed",
+        TestOption::default(),
+    )
+}
+
+#[test]
+fn time_pipeline() {
+    check_contains(
+        "TIMEFORMAT='100%%'\ntime ! true",
+        "100%",
+        TestOption::default().err().code(1),
+    );
+    check_contains(
+        "TIMEFORMAT='took %0R seconds'\ntime sleep 1 | cat",
+        "took 1 seconds",
+        TestOption::default().err(),
+    )
+}
+
+#[test]
+fn test_builtin() {
+    let tmp_dir = tempdir().unwrap();
+    File::create(tmp_dir.path().join("file")).unwrap();
+
+    check_contains(
+        r#"
+test -f file && echo file
+[ -d file ] || echo not a directory
+if [ "$missing" = "" -a ! -e missing ]; then echo empty; fi
+[ 2 -lt 10 ] && test 2 \< 10 || echo compared
+"#,
+        r#"
+file
+not a directory
+empty
+compared
+"#,
+        TestOption::no_path().current_dir(tmp_dir.into_path()),
+    );
+    check_contains(
+        "[ 1 -eq 1\ntest a -eq 1",
+        "[: missing `]'\ntest: a: integer expression expected",
+        TestOption::no_path().err().code(2),
+    )
+}
+
+#[test]
+fn conditional_command() {
+    check_contains(
+        r#"
+x=abc y=12-ab
+[[ $x == a* && $y =~ ^([0-9]+)-([a-z]+)$ ]] && echo ${BASH_REMATCH[2]} ${BASH_REMATCH[1]}
+[[ $x == "a*" ]] || echo quoted
+[[ $x =~ "." ]] || echo literal
+[[ -z $missing && ( b < a || ! -e /nonexistent ) ]] && echo grouped
+[[ $x != abc ]] || echo equal
+[[ a =~ ( ]] || echo invalid
+"#,
+        r#"
+ab 12
+quoted
+literal
+grouped
+equal
+invalid
 "#,
         TestOption::no_path(),
     )
 }
+
+#[test]
+fn exit_status() {
+    check_contains(
+        r#"
+false; echo $?
+! true; echo $?
+missing_command; echo $?
+cd /nonexistent 2> /dev/null; echo $?
+true | false | true; echo $? ${PIPESTATUS[@]}
+set -o pipefail
+true | false | true; echo $? ${PIPESTATUS[1]}
+sh -c 'exit 3' | true; echo $?
+set +o
+set +o pipefail
+set +o
+true | false | true; echo $?
+"#,
+        r#"
+1
+1
+missing_command: command not found
+127
+1
+0 0 1 0
+1 1
+3
+set +o emacs
+set +o errexit
+set +o ignoreeof
+set +o monitor
+set +o noglob
+set +o nounset
+set -o pipefail
+set +o vi
+set +o xtrace
+set +o emacs
+set +o errexit
+set +o ignoreeof
+set +o monitor
+set +o noglob
+set +o nounset
+set +o pipefail
+set +o vi
+set +o xtrace
+0
+"#,
+        TestOption::default(),
+    );
+    check_contains(
+        "echo before; false; exit",
+        "before",
+        TestOption::default().code(1),
+    )
+}
+
+#[test]
+fn builtins_read_stdin() {
+    check_contains(
+        r#"
+printf 'a b c\nd e\n' | while read x y; do echo "[$x] [$y]"; done
+seq 3 | { read first; read second; echo $second; cat; }
+printf 'x:y:z' | { IFS=:; read p q; echo "$p $q $?"; }
+echo 'echo sourced; false' | source /dev/stdin; echo $?
+"#,
+        r#"
+[a] [b c]
+[d] [e]
+2
+3
+x y:z 1
+sourced
+1
+"#,
+        TestOption::default(),
+    );
+    check_contains(
+        "read 1x",
+        "read: `1x': not a valid identifier",
+        TestOption::no_path().err().code(1),
+    )
+}
+
+#[test]
+fn read_options() {
+    check_contains(
+        r#"
+echo 'a,b,c' | { IFS=, read x y; echo "$x|$y|$IFS|"; }
+echo 'a\tb' | { read -r raw; echo "$raw"; }
+printf 'one two  three\n' | { read -a words; echo ${words[2]} ${words[0]}; }
+printf 'x;y' | { read -d ';' first; read second; echo $first $second $?; }
+printf 'abcdef' | { read -n 3 start; echo $start; }
+sleep 1 | { read -t 0.1 line; echo $?; }
+read -p 'not a terminal' -s silent < /dev/null; echo $?
+"#,
+        r#"
+a|b,c||
+a\tb
+three one
+x y 1
+abc
+142
+1
+"#,
+        TestOption::default(),
+    )
+}
+
+#[test]
+fn job_control() {
+    check_contains(
+        r#"
+sleep 0.5 &
+[ -n "$!" ] && echo pid
+jobs
+jobs -x
+"#,
+        r#"
+pid
+[1]+  Running                 sleep 0.5 &
+"#,
+        TestOption::default().code(2),
+    );
+    check_contains(
+        "sleep 0.1 & fg; bg %2",
+        "fg: no job control\nbg: no job control",
+        TestOption::default().err().code(1),
+    )
+}
+
+#[test]
+fn interrupt() -> Result<()> {
+    let command = Command::cargo_bin("codecrafters-shell")?;
+    let path = Path::new(command.get_program()).to_str().unwrap();
+    let mut p = rexpect::spawn(path, Some(2000))?;
+
+    // Ctrl-C discards the line being edited
+    p.exp_string("$ ")?;
+    p.send("echo discarded")?;
+    p.send_control('c')?;
+    p.exp_string("$ \r")?;
+    p.send_line("echo $?")?;
+    p.exp_string("130")?;
+
+    // and only interrupts the running command
+    p.exp_string("$ \r")?;
+    p.send_line("sleep 5; echo skipped")?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    p.send_control('c')?;
+    p.exp_string("$ \r")?;
+    p.send_line("echo status $?")?;
+    p.exp_string("status 130")?;
+
+    // a command exiting with the same status is not interrupted
+    p.exp_string("$ \r")?;
+    p.send_line("sh -c 'exit 130'; echo after $?")?;
+    p.exp_string("after 130")?;
+
+    // nor is a subshell, unless it gets Ctrl-C
+    p.exp_string("$ \r")?;
+    p.send_line("(sleep 5; echo skipped); echo skipped")?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    p.send_control('c')?;
+    p.exp_string("$ \r")?;
+    p.send_line("echo subshell $?")?;
+    p.exp_string("subshell 130")?;
+
+    p.exp_string("$ \r")?;
+    p.send_line("exit")?;
+    p.exp_eof()?;
+    Ok(())
+}
+
+#[test]
+fn terminal_passthrough() -> Result<()> {
+    let command = Command::cargo_bin("codecrafters-shell")?;
+    let path = Path::new(command.get_program()).to_str().unwrap();
+    let mut p = rexpect::spawn(path, Some(2000))?;
+
+    // a child gets the terminal itself when its streams are not redirected,
+    // outputs differ from the command lines which are echoed
+    p.exp_string("$ \r")?;
+    p.send_line("sh -c 'test -t 0 && test -t 1 && test -t 2 && echo tty$((1 + 1))'")?;
+    p.exp_string("tty2")?;
+    p.exp_string("$ \r")?;
+    p.send_line("sh -c 'test -t 0 || echo redirected$((1 + 1))' < /dev/null")?;
+    p.exp_string("redirected2")?;
+
+    // and the modes it changes are restored afterwards
+    p.exp_string("$ \r")?;
+    p.send_line("stty -icanon; stty -a | grep -c -- -icanon | sed 's/^/raw modes: /'")?;
+    p.exp_string("raw modes: 0")?;
+
+    p.exp_string("$ \r")?;
+    p.send_line("exit")?;
+    p.exp_eof()?;
+    Ok(())
+}
+
+#[test]
+fn read_terminal() -> Result<()> {
+    let command = Command::cargo_bin("codecrafters-shell")?;
+    let path = Path::new(command.get_program()).to_str().unwrap();
+    let mut p = rexpect::spawn(path, Some(2000))?;
+
+    // `-n` and `-d` do not wait for the end of the line
+    p.exp_string("$ \r")?;
+    p.send_line("read -n 2 a; echo; echo got$a$a")?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    p.send("xy")?;
+    p.flush()?;
+    p.exp_string("gotxyxy")?;
+    p.exp_string("$ \r")?;
+    p.send_line("read -s -d . a; echo got$a$a")?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    p.send("zw.")?;
+    p.flush()?;
+    p.exp_string("gotzwzw")?;
+
+    p.exp_string("$ \r")?;
+    p.send_line("exit")?;
+    p.exp_eof()?;
+    Ok(())
+}
+
+#[test]
+fn command_paths() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp_dir = tempdir().unwrap();
+    let script = tmp_dir.path().join("run.sh");
+    fs::write(&script, "#!/bin/sh\necho \"ran $1\"\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(tmp_dir.path().join("noexec"), "echo no\n").unwrap();
+    fs::create_dir(tmp_dir.path().join("dir")).unwrap();
+
+    // an empty PATH is the current directory
+    check_contains(
+        &format!(
+            r#"
+type run.sh
+./run.sh a
+run.sh b
+{} c
+./noexec; echo $?
+./missing; echo $?
+./dir; echo $?
+"#,
+            script.display()
+        ),
+        r#"
+run.sh is ./run.sh
+ran a
+ran b
+ran c
+126
+127
+126
+"#,
+        TestOption::no_path().current_dir(tmp_dir.path().into()),
+    );
+    check_contains(
+        "./noexec; ./missing; ./dir",
+        r#"
+./noexec: Permission denied
+./missing: No such file or directory
+./dir: Is a directory
+"#,
+        TestOption::no_path()
+            .current_dir(tmp_dir.path().into())
+            .err()
+            .code(126),
+    );
+    // the command is searched in a PATH assigned for it, without hashing it
+    check_contains(
+        &format!(
+            "PATH={}:$PATH run.sh d; hash; run.sh e",
+            tmp_dir.path().display()
+        ),
+        r#"
+ran d
+hash: hash table empty
+run.sh: command not found
+"#,
+        TestOption::default().env("PATH", "/usr/bin").code(127),
+    );
+}
+
+#[test]
+fn hash_table() {
+    check_contains(
+        r#"
+hash
+ls > /dev/null; ls > /dev/null
+hash
+type ls
+hash -p /bin/echo greet
+greet hello
+hash -t greet
+hash -l
+PATH=$PATH
+hash
+hash missing_command; echo $?
+"#,
+        r#"
+hash: hash table empty
+hits	command
+   2	/usr/bin/ls
+ls is hashed (/usr/bin/ls)
+hello
+/bin/echo
+builtin hash -p /bin/echo greet
+builtin hash -p /usr/bin/ls ls
+hash: hash table empty
+1
+"#,
+        TestOption::default().env("PATH", "/usr/bin"),
+    );
+    check_contains(
+        "hash -d ls",
+        "hash: ls: not found",
+        TestOption::default().err().code(1),
+    )
+}
+
+#[test]
+fn run_script() {
+    let tmp_dir = tempdir().unwrap();
+    let script = tmp_dir.path().join("script.sh");
+    fs::write(
+        &script,
+        r#"#!/usr/bin/env codecrafters-shell
+echo "$# ${10} $2"
+for arg in "$@"; do echo "[$arg]"; done
+shift 8
+echo $* $?
+shift 3 || echo out of range
+for arg; do echo "<$arg>"; done
+"#,
+    )
+    .unwrap();
+
+    let args = ["a", "b c", "3", "4", "5", "6", "7", "8", "9", "ten"];
+    let option = args.iter().fold(
+        TestOption::default().arg(script.to_str().unwrap()),
+        |option, arg| option.arg(arg),
+    );
+    check_contains(
+        "",
+        r#"
+10 ten b c
+[a]
+[b c]
+[3]
+[4]
+[5]
+[6]
+[7]
+[8]
+[9]
+[ten]
+9 ten 0
+out of range
+<9>
+<ten>
+"#,
+        option,
+    );
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .arg("missing.sh")
+        .assert()
+        .code(127)
+        .stderr("missing.sh: No such file or directory\n");
+
+    // commands run one at a time, up to a syntax error
+    let broken = tmp_dir.path().join("broken.sh");
+    fs::write(&broken, "alias hi='echo hello'\nhi\nfi\necho skipped\n").unwrap();
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .arg(&broken)
+        .assert()
+        .code(2)
+        .stdout("hello\n")
+        .stderr(format!(
+            "{}: line 3: syntax error near unexpected token `fi'\n",
+            broken.display()
+        ));
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .args(["-c", "alias ll='echo hi'\nll\nif true\nthen ll"])
+        .assert()
+        .code(2)
+        .stdout("hi\n")
+        .stderr("-c: line 4: syntax error: unexpected end of file\n");
+}
+
+#[test]
+fn command_line_options() {
+    check_contains(
+        "",
+        "name a 2",
+        TestOption::default()
+            .arg("-c")
+            .arg("echo $0 $1 $#")
+            .arg("name")
+            .arg("a")
+            .arg("b"),
+    );
+    check_contains(
+        "echo $1 $2",
+        "first second",
+        TestOption::default().arg("-s").arg("first").arg("second"),
+    );
+    check_contains(
+        "false | true; echo $?",
+        "1",
+        TestOption::default().arg("-o").arg("pipefail"),
+    );
+
+    // errexit does not apply to conditions and to the left of `&&` or `||`
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .args([
+            "-ec",
+            "if false; then :; fi; false || true; false && :; ! true; echo ok; false; echo no",
+        ])
+        .assert()
+        .code(1)
+        .stdout("ok\n");
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .args(["-x", "-c", "A=1 echo 'a b' c"])
+        .assert()
+        .success()
+        .stdout("a b c\n")
+        .stderr("+ A=1 echo 'a b' c\n");
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .arg("-z")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::ends_with("-z: invalid option\n"));
+}
+
+#[test]
+fn non_interactive() {
+    // no prompt, commands read the rest of the input
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .write_stdin("echo start\nread line\nnot a command\nif true\nthen echo $line\nfi\nfalse\n")
+        .assert()
+        .code(1)
+        .stdout("start\nnot a command\n");
+    // a backslash at the end of a line continues the command
+    let continued = "echo ab\\\ncd \"x\\\ny\" \\\n  z\necho end\\\n";
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .write_stdin(continued)
+        .assert()
+        .success()
+        .stdout("abcd xy z\nend\n");
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .args(["-c", continued])
+        .assert()
+        .success()
+        .stdout("abcd xy z\nend\n");
+    // the same for an interactive shell reading to the end of its input
+    check_contains(
+        "echo before; false",
+        "before",
+        TestOption::default().arg("-i").code(1),
+    );
+}
+
+#[test]
+fn source_file() {
+    let tmp_dir = tempdir().unwrap();
+    fs::write(
+        tmp_dir.path().join("lib.sh"),
+        r#"echo sourced $# $1
+cd /
+name=value
+alias greet='echo hello'
+for arg in "$@"; do
+  if [ "$arg" = stop ]; then return 4; fi
+done
+echo end
+"#,
+    )
+    .unwrap();
+
+    let path = format!("{}:/usr/bin", tmp_dir.path().display());
+    check_contains(
+        r#"
+source lib.sh a b; echo $? $# $1
+echo $name; pwd
+greet
+. lib.sh go stop; echo $?
+return 1
+"#,
+        r#"
+sourced 2 a
+end
+0 2 x
+value
+/
+hello
+sourced 2 go
+4
+"#,
+        TestOption::default()
+            .env("PATH", &path)
+            .arg("-s")
+            .arg("x")
+            .arg("y")
+            .code(1),
+    );
+}
+
+#[test]
+fn startup_files() {
+    let home = tempdir().unwrap();
+    let write = |name: &str, contents: &str| fs::write(home.path().join(name), contents).unwrap();
+    write(".profile", "echo profile\nalias hi='echo hello'\n");
+    write(".codecraftersshrc", "echo rc\n");
+    write(".codecrafters_logout", "echo logout\n");
+    write("custom", "echo custom\nreturn\necho skipped\n");
+    let home_dir = home.path().to_str().unwrap();
+
+    check_contains(
+        "hi",
+        "profile\nrc\nhello\nlogout",
+        TestOption::default()
+            .env("HOME", home_dir)
+            .arg("-l")
+            .arg("-i"),
+    );
+    // errexit leaves the shell the way `exit` does
+    check_contains(
+        "set -e; false; echo skipped",
+        "profile\nrc\nlogout",
+        TestOption::default()
+            .env("HOME", home_dir)
+            .arg("-l")
+            .arg("-i")
+            .code(1),
+    );
+    check_contains(
+        "echo done",
+        "custom\ndone",
+        TestOption::default()
+            .env("HOME", home_dir)
+            .arg("-i")
+            .arg("--rcfile")
+            .arg(&format!("{home_dir}/custom")),
+    );
+    // only interactive shells read the rc file
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .env("HOME", home_dir)
+        .write_stdin("echo done")
+        .assert()
+        .success()
+        .stdout("done\n");
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .env("HOME", home_dir)
+        .args(["-i", "--norc"])
+        .write_stdin("echo done")
+        .assert()
+        .success()
+        .stdout("done\n");
+}
+
+#[test]
+fn variables() {
+    check_contains(
+        r#"
+local=1; export exported=2
+sh -c 'echo [$local] [$exported]'
+export local; sh -c 'echo [$local]'
+export -n local; sh -c 'echo [$local]'
+export later; later=3; sh -c 'echo [$later]'
+unset later; later=4; sh -c 'echo [$later]'
+export | grep later; echo $?
+unset exported; echo [$exported]
+spaced='a b'; set | grep spaced
+unset 1x
+"#,
+        r#"
+[] [2]
+[1]
+[]
+[3]
+[]
+1
+[]
+spaced='a b'
+"#,
+        TestOption::default().code(1),
+    );
+}
+
+#[test]
+fn shell_options() {
+    let tmp_dir = tempdir().unwrap();
+    for path in ["a.txt", ".hidden", "sub/b.txt"] {
+        let path = tmp_dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap();
+    }
+    check_contains(
+        r#"
+echo *.none; shopt -s nullglob; echo before *.none after; shopt -u nullglob
+shopt -s dotglob; echo *; shopt -u dotglob
+echo **/*.txt; shopt -s globstar; echo **/*.txt
+shopt globstar nullglob; echo $?
+shopt -p dotglob
+set -f; echo *; set +f
+set -eo pipefail; set -o | grep -E '^(errexit|pipefail)'; set +e
+set -- a 'b c'; echo $# $2
+shopt -s cdspell; cd sbu; pwd
+"#,
+        &format!(
+            r#"
+*.none
+before after
+.hidden a.txt sub
+sub/b.txt
+a.txt sub/b.txt
+globstar       	on
+nullglob       	off
+1
+shopt -u dotglob
+*
+errexit        	on
+pipefail       	on
+2 b c
+sub
+{}/sub
+"#,
+            tmp_dir.path().display()
+        ),
+        TestOption::default().current_dir(tmp_dir.path().to_path_buf()),
+    );
+
+    // an unset variable stops a non-interactive shell
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .args(["-u", "-c", "echo ${set}; echo $unset; echo skipped"])
+        .env("set", "value")
+        .assert()
+        .code(127)
+        .stdout("value\n")
+        .stderr("unset: unbound variable\n");
+    // `$-` is always set, to the enabled flags and how the shell was started
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .args(["-u", "-c", "echo $-; set -f +u; echo ${-}"])
+        .assert()
+        .success()
+        .stdout("uc\nfc\n");
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .write_stdin("set -x; echo $-")
+        .assert()
+        .success()
+        .stdout("xs\n");
+    // the message goes where stderr is redirected
+    Command::cargo_bin("codecrafters-shell")
+        .unwrap()
+        .args(["-u", "-c", "{ echo $unset; } 2>err"])
+        .current_dir(tmp_dir.path())
+        .assert()
+        .code(127)
+        .stderr("");
+    assert_eq!(
+        fs::read_to_string(tmp_dir.path().join("err")).unwrap(),
+        "unset: unbound variable\n"
+    );
+}